@@ -8,7 +8,7 @@ use std::thread::JoinHandle;
 use clap::Parser;
 use ctrlc;
 use midi::MidiProvider;
-use midi::{MidiIn, MidiPlayer};
+use midi::{LogPlayer, Meta, MidiIn, MidiPlayer, OscIn, RtpMidiIn, Score, StreamIn};
 use options::Options;
 use renderer::curses::CursesRenderer;
 use renderer::text::TextRenderer;
@@ -20,11 +20,44 @@ mod renderer;
 mod renderer_lib;
 
 type Message = Vec<u8>;
+/// Index of the input a message came from, in the order the ports were given.
+type SourceId = usize;
 
 #[derive(Debug)]
 struct MidiData {
     message: Message,
     timestamp: Duration,
+    source: SourceId,
+}
+
+/// News for renderers that is not a MIDI message.
+#[derive(Debug, Clone)]
+enum Notice {
+    /// An input port is delivering messages
+    Connected { source: SourceId, port: String },
+    /// An input port went away; it is reconnected when it comes back
+    Disconnected { source: SourceId, port: String },
+    /// The MIDI file says something about the music
+    Meta { meta: Meta, timestamp: Duration },
+    /// Playback jumped; what was said about the music may no longer hold
+    Seeked { position: Duration },
+    /// Another file of the playlist is playing, from the start
+    Song {
+        /// File name, without its directory
+        name: String,
+        /// Counting from 0
+        index: usize,
+        count: usize,
+        score: Arc<Score>,
+    },
+    /// A file of the playlist could not be played
+    Skipped { name: String, reason: String },
+    /// The input is being recorded to a file
+    Recording { path: String },
+    /// A recording is written, unless there was an error
+    Recorded { path: String, error: Option<String> },
+    /// Logging the input stopped, writing the event log failed
+    LogFailed { path: String, error: String },
 }
 
 fn render_init<T: MidiProvider>(
@@ -44,23 +77,56 @@ fn render_init<T: MidiProvider>(
 
 fn main() {
     let opts: Options = Options::parse();
+    if let Err(e) = opts.check() {
+        e.exit();
+    }
+    if opts.list_ports {
+        if let Err(e) = midi::list_ports() {
+            eprintln!("Failed to list MIDI ports: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if opts.list_sequences {
+        if let Err(e) = midi::list_sequences(&opts) {
+            eprintln!("mirmidivi-rs: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let quit = Arc::new(AtomicBool::new(false));
-    let mut handlers = Vec::<JoinHandle<()>>::new();
     let q = quit.clone();
     let _ = ctrlc::set_handler(move || {
         q.store(true, SeqCst);
     });
 
-    match &opts.midifile {
-        Some(_) => {
-            let midi = MidiPlayer::new(&opts);
-            render_init(&opts, &midi, quit.clone(), &mut handlers);
-        }
-        None => {
-            let midi = MidiIn::new(&opts);
-            render_init(&opts, &midi, quit.clone(), &mut handlers);
+    if !opts.midifile.is_empty() {
+        run::<MidiPlayer>(&opts, quit);
+    } else if opts.replay.is_some() {
+        run::<LogPlayer>(&opts, quit);
+    } else if opts.stream.is_some() {
+        run::<StreamIn>(&opts, quit);
+    } else if opts.rtp_midi.is_some() {
+        run::<RtpMidiIn>(&opts, quit);
+    } else if opts.osc.is_some() {
+        run::<OscIn>(&opts, quit);
+    } else {
+        run::<MidiIn>(&opts, quit);
+    }
+}
+
+/// Render `T` until quit is requested, keeping it alive meanwhile.
+fn run<T: MidiProvider>(opts: &Options, quit: Arc<AtomicBool>) {
+    let mut handlers = Vec::<JoinHandle<()>>::new();
+    let midi = match T::new(opts) {
+        Ok(midi) => midi,
+        Err(e) => {
+            eprintln!("mirmidivi-rs: {}", e);
+            std::process::exit(1);
         }
     };
+    render_init(opts, &midi, quit.clone(), &mut handlers);
 
     loop {
         if quit.load(SeqCst) {
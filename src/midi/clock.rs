@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use time::Duration;
+
+/// Where renderers are in time: the song position when playing a file, or
+/// the time since start when listening.
+#[derive(Clone)]
+pub struct Clock {
+    state: Arc<RwLock<ClockState>>,
+}
+
+struct ClockState {
+    /// When `position` was reached
+    anchor: Instant,
+    position: Duration,
+    running: bool,
+    /// How much faster than real time the clock runs
+    speed: f64,
+}
+
+impl Clock {
+    /// A clock running from zero, starting now.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(RwLock::new(ClockState {
+                anchor: Instant::now(),
+                position: Duration::ZERO,
+                running: true,
+                speed: 1.0,
+            })),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        let state = self.state.read().unwrap();
+        if state.running {
+            state.position + Duration::try_from(state.anchor.elapsed()).unwrap() * state.speed
+        } else {
+            state.position
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.read().unwrap().running
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.state.read().unwrap().speed
+    }
+
+    /// Jump to `position`, and run from there or stand still.
+    pub fn set(&self, position: Duration, running: bool) {
+        let mut state = self.state.write().unwrap();
+        state.anchor = Instant::now();
+        state.position = position;
+        state.running = running;
+    }
+
+    /// Run `speed` times as fast as real time from now on.
+    pub fn set_speed(&self, speed: f64) {
+        let now = self.now();
+        let mut state = self.state.write().unwrap();
+        state.anchor = Instant::now();
+        state.position = now;
+        state.speed = speed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use time::Duration;
+
+    use super::Clock;
+
+    #[test]
+    fn clock_speed() {
+        let clock = Clock::new();
+        clock.set(Duration::seconds(10), false);
+        clock.set_speed(0.5);
+        assert_eq!(clock.now(), Duration::seconds(10));
+
+        clock.set(Duration::seconds(10), true);
+        sleep(Duration::milliseconds(200).unsigned_abs());
+        let now = clock.now();
+        assert!(now >= Duration::milliseconds(10_100));
+        assert!(now < Duration::milliseconds(10_200));
+    }
+}
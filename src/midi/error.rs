@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{error::Error, fmt, io};
+
+/// Why a MIDI provider could not be set up.
+#[derive(Debug)]
+pub enum MidiError {
+    /// The MIDI file does not exist
+    FileNotFound { path: String },
+    /// A directory given as MIDI files has none in it
+    NoMidiFiles { path: String },
+    /// The MIDI file exists but could not be read
+    Io { path: String, source: io::Error },
+    /// The MIDI file is malformed, from `offset` bytes in
+    Parse {
+        path: String,
+        offset: usize,
+        reason: String,
+    },
+    /// The MIDI file counts time in a way it cannot be played by
+    UnsupportedTiming { path: String, timing: String },
+    /// There is no MIDI port at all
+    NoPorts { direction: &'static str },
+    /// No MIDI port matches what was asked for
+    NoMatchingPort {
+        direction: &'static str,
+        spec: String,
+        available: Vec<String>,
+    },
+    /// A MIDI port was asked for by an index past the last port
+    PortOutOfRange {
+        direction: &'static str,
+        index: usize,
+        count: usize,
+    },
+    /// The MIDI system refused to do something
+    Backend(String),
+    /// A place in the MIDI file could not be found
+    Position { spec: String, reason: String },
+    /// A sequence was asked for by a number past the last sequence
+    Sequence { sequence: usize, count: usize },
+    /// A network address could not be listened on
+    Socket { address: String, source: io::Error },
+    /// The event log is malformed at `line`, counting from 1
+    Log {
+        path: String,
+        line: usize,
+        reason: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, MidiError>;
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiError::FileNotFound { path } => write!(f, "{}: no such MIDI file", path),
+            MidiError::NoMidiFiles { path } => write!(f, "{}: no MIDI files in there", path),
+            MidiError::Io { path, source } => write!(f, "{}: {}", path, source),
+            MidiError::Parse {
+                path,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "{}: not a valid MIDI file at byte {}: {}",
+                path, offset, reason
+            ),
+            MidiError::UnsupportedTiming { path, timing } => {
+                write!(f, "{}: unsupported timing, {}", path, timing)
+            }
+            MidiError::NoPorts { direction } => write!(f, "No MIDI {} port found", direction),
+            MidiError::NoMatchingPort {
+                direction,
+                spec,
+                available,
+            } => write!(
+                f,
+                "No MIDI {} port matches \"{}\" (available: {})",
+                direction,
+                spec,
+                available.join(", ")
+            ),
+            MidiError::PortOutOfRange {
+                direction,
+                index,
+                count,
+            } => write!(
+                f,
+                "MIDI {} port index {} is out of range (0..{})",
+                direction, index, count
+            ),
+            MidiError::Backend(message) => f.write_str(message),
+            MidiError::Position { spec, reason } => {
+                write!(f, "Cannot go to \"{}\" in the MIDI file: {}", spec, reason)
+            }
+            MidiError::Sequence { sequence, count } => write!(
+                f,
+                "The MIDI file has no sequence {} (sequences are 1..={})",
+                sequence, count
+            ),
+            MidiError::Socket { address, source } => {
+                write!(f, "Cannot listen on {}: {}", address, source)
+            }
+            MidiError::Log { path, line, reason } => write!(
+                f,
+                "{}: not a valid event log at line {}: {}",
+                path, line, reason
+            ),
+        }
+    }
+}
+
+impl Error for MidiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MidiError::Io { source, .. } | MidiError::Socket { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use time::Duration;
+
+use super::error::{MidiError, Result};
+use crate::{MidiData, Notice};
+
+/// First line of every event log, naming the format and its version.
+const HEADER: &str = "# mirmidivi-rs event log 1";
+
+/// Lossless log of live MIDI, for replaying a session exactly as it was.
+///
+/// An event log is text, a line per message: its timestamp in
+/// microseconds, its source, then its bytes in hex, e.g.
+/// `1500000 0 90 3c 64`, after a header line naming the format. Other lines
+/// starting with `#` are comments.
+pub struct EventLog;
+
+impl EventLog {
+    /// Pass on the messages from `midi_recv`, logging them to `path`
+    /// meanwhile.
+    ///
+    /// Once `midi_recv` disconnects, the returned receiver disconnects too.
+    /// Should writing fail, logging stops and [`Notice::LogFailed`] says why.
+    pub fn tap(
+        path: &str,
+        midi_recv: Receiver<MidiData>,
+        notice_send: Sender<Notice>,
+    ) -> Result<(Receiver<MidiData>, JoinHandle<()>)> {
+        let io_error = |source| MidiError::Io {
+            path: path.to_owned(),
+            source,
+        };
+        let mut file = LineWriter::new(File::create(path).map_err(io_error)?);
+        writeln!(file, "{}", HEADER).map_err(io_error)?;
+
+        let path = path.to_owned();
+        let (midi_send, tapped_recv) = unbounded();
+        let handler = thread::spawn(move || {
+            let mut file = Some(file);
+            midi_recv.iter().for_each(|midi| {
+                if let Some(writer) = &mut file {
+                    if let Err(e) = writeln!(writer, "{}", format_line(&midi)) {
+                        file = None;
+                        let _send = notice_send.send(Notice::LogFailed {
+                            path: path.clone(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+                let _send = midi_send.send(midi);
+            });
+        });
+        Ok((tapped_recv, handler))
+    }
+
+    /// Read the messages of an event log, in the order they were logged.
+    /// Logs without the header of this version of the format are refused.
+    pub fn parse(path: &str, log: &str) -> Result<Vec<MidiData>> {
+        if log.lines().next().map(str::trim_end) != Some(HEADER) {
+            return Err(MidiError::Log {
+                path: path.to_owned(),
+                line: 1,
+                reason: format!("expected the header \"{}\"", HEADER),
+            });
+        }
+        log.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| {
+                parse_line(line).map_err(|reason| MidiError::Log {
+                    path: path.to_owned(),
+                    line: index + 1,
+                    reason,
+                })
+            })
+            .collect()
+    }
+}
+
+fn format_line(midi: &MidiData) -> String {
+    let bytes: Vec<String> = midi
+        .message
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!(
+        "{} {} {}",
+        midi.timestamp.whole_microseconds(),
+        midi.source,
+        bytes.join(" ")
+    )
+}
+
+fn parse_line(line: &str) -> std::result::Result<MidiData, String> {
+    let mut fields = line.split_whitespace();
+    let timestamp = fields
+        .next()
+        .and_then(|field| field.parse().ok())
+        .ok_or("no timestamp in microseconds")?;
+    let source = fields
+        .next()
+        .and_then(|field| field.parse().ok())
+        .ok_or("no source")?;
+    let message = fields
+        .map(|field| u8::from_str_radix(field, 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|e| format!("not a byte in hex: {}", e))?;
+    if message.is_empty() {
+        return Err("no message".to_owned());
+    }
+    Ok(MidiData {
+        message,
+        timestamp: Duration::microseconds(timestamp),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crossbeam_channel::unbounded;
+    use time::Duration;
+
+    use super::EventLog;
+    use crate::midi::test_util::TempPath;
+    use crate::midi::MidiError;
+    use crate::MidiData;
+
+    #[test]
+    fn event_log_round_trip() {
+        let log_path = TempPath::new(".txt");
+        let path = log_path.to_str();
+        let sent = vec![
+            (1_000, 0, vec![0x90, 0x3C, 0x64]),
+            (1_250_001, 1, vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]),
+            (1_250_001, 1, vec![0xF8]),
+        ];
+
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let (tapped_recv, handler) = EventLog::tap(path, midi_recv, notice_send).unwrap();
+        sent.iter().for_each(|(timestamp, source, message)| {
+            midi_send
+                .send(MidiData {
+                    message: message.clone(),
+                    timestamp: Duration::microseconds(*timestamp),
+                    source: *source,
+                })
+                .unwrap();
+        });
+        drop(midi_send);
+        handler.join().unwrap();
+        assert_eq!(tapped_recv.iter().count(), 3);
+        assert!(notice_recv.try_recv().is_err());
+
+        let log = fs::read_to_string(path).unwrap();
+        assert!(log.contains("\n1250001 1 f0 7e 7f 09 01 f7\n"));
+        let read: Vec<_> = EventLog::parse(path, &log)
+            .unwrap()
+            .into_iter()
+            .map(|midi| {
+                (
+                    midi.timestamp.whole_microseconds() as i64,
+                    midi.source,
+                    midi.message,
+                )
+            })
+            .collect();
+        assert_eq!(read, sent);
+
+        let parse = |log: &str| EventLog::parse("log", log);
+        assert!(matches!(
+            parse("# mirmidivi-rs event log 1\n# comment\n\n1000 0 90 3c 64\n1000 0 9g\n"),
+            Err(MidiError::Log { line: 5, .. })
+        ));
+        // Without the header, or of another version.
+        assert!(matches!(
+            parse("1000 0 90 3c 64\n"),
+            Err(MidiError::Log { line: 1, .. })
+        ));
+        assert!(matches!(
+            parse("# mirmidivi-rs event log 2\n1000 0 90 3c 64\n"),
+            Err(MidiError::Log { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::Arc;
+
+use crossbeam_channel::{select, tick, Receiver, Sender};
+use time::Duration;
+
+use super::clock::Clock;
+use super::error::Result;
+use super::input_backend::{InputBackend, InputCallback, InputConnection};
+use super::thru::Thru;
+use crate::{MidiData, Notice, SourceId};
+
+/// A selected input port and its connection, if it is plugged in.
+struct Port {
+    name: String,
+    connection: Option<Box<dyn InputConnection>>,
+    /// Our own port, which cannot be unplugged
+    is_virtual: bool,
+}
+
+/// Keeps the selected input ports connected across unplugging and replugging.
+///
+/// The supervisor owns every connection, so dropping it disconnects them all.
+pub struct Supervisor {
+    backend: Arc<dyn InputBackend>,
+    midi_send: Sender<MidiData>,
+    notice_send: Sender<Notice>,
+    thru: Option<Thru>,
+    clock: Clock,
+    ports: Vec<Port>,
+}
+
+/// Name of the device behind a port.
+///
+/// ALSA appends the sequencer address ("20:0") to port names, and the client
+/// number changes when a device is plugged in again.
+fn device_name(name: &str) -> &str {
+    let is_address = |s: &str| {
+        s.split_once(':').is_some_and(|(client, port)| {
+            [client, port]
+                .iter()
+                .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        })
+    };
+    match name.rsplit_once(' ') {
+        Some((device, address)) if is_address(address) => device,
+        _ => name,
+    }
+}
+
+impl Supervisor {
+    pub fn new(
+        backend: Arc<dyn InputBackend>,
+        midi_send: Sender<MidiData>,
+        notice_send: Sender<Notice>,
+        thru: Option<Thru>,
+        clock: Clock,
+    ) -> Self {
+        Self {
+            backend,
+            midi_send,
+            notice_send,
+            thru,
+            clock,
+            ports: Vec::new(),
+        }
+    }
+
+    /// Connect to the port at `index` as the next source.
+    pub fn add(&mut self, index: usize, name: &str) -> Result<()> {
+        let source = self.ports.len();
+        let connection = self.backend.connect(index, name, self.callback(source))?;
+        self.connected(source, name);
+        self.ports.push(Port {
+            name: name.to_owned(),
+            connection: Some(connection),
+            is_virtual: false,
+        });
+        Ok(())
+    }
+
+    /// Create a virtual port called `name` as the next source.
+    pub fn add_virtual(&mut self, name: &str) -> Result<()> {
+        let source = self.ports.len();
+        let connection = self.backend.create_virtual(name, self.callback(source))?;
+        self.connected(source, name);
+        self.ports.push(Port {
+            name: name.to_owned(),
+            connection: Some(connection),
+            is_virtual: true,
+        });
+        Ok(())
+    }
+
+    /// Forward messages from a new connection as `source`.
+    fn callback(&self, source: SourceId) -> InputCallback {
+        let midi_send = self.midi_send.clone();
+        let thru = self.thru.clone();
+        // Timestamps are relative to the connection, so shift them onto the clock.
+        let offset = self.clock.now();
+
+        Box::new(move |stamp, message| {
+            if let Some(thru) = &thru {
+                thru.send(message);
+            }
+            let _send = midi_send.send(MidiData {
+                message: message.to_vec(),
+                timestamp: offset + Duration::microseconds(stamp as i64),
+                source,
+            });
+        })
+    }
+
+    fn connected(&self, source: SourceId, name: &str) {
+        let _send = self.notice_send.send(Notice::Connected {
+            source,
+            port: name.to_owned(),
+        });
+    }
+
+    /// Check the port list once, dropping vanished ports and reconnecting
+    /// the ones that came back.
+    ///
+    /// A port unplugged and plugged in again since the last check shows
+    /// under a new ALSA address, and is reconnected as well: once its own
+    /// name is gone, a port of the same device that no other source holds
+    /// is taken for it.
+    pub fn poll(&mut self) {
+        let names = match self.backend.port_names() {
+            Ok(names) => names,
+            Err(_) => return,
+        };
+
+        for source in 0..self.ports.len() {
+            if self.ports[source].is_virtual {
+                continue;
+            }
+            let exact = names
+                .iter()
+                .position(|name| *name == self.ports[source].name);
+            let device = device_name(&self.ports[source].name);
+            let held = |name: &String| self.ports.iter().any(|port| port.name == *name);
+            let moved = || {
+                names
+                    .iter()
+                    .position(|name| device_name(name) == device && !held(name))
+            };
+            let found = exact.or_else(moved);
+            let replugged = exact.is_none() && found.is_some();
+            if self.ports[source].connection.is_some() && (found.is_none() || replugged) {
+                self.ports[source].connection = None;
+                let _send = self.notice_send.send(Notice::Disconnected {
+                    source,
+                    port: self.ports[source].name.clone(),
+                });
+            }
+            if let (Some(index), None) = (found, &self.ports[source].connection) {
+                let callback = self.callback(source);
+                if let Ok(connection) = self.backend.connect(index, &names[index], callback) {
+                    self.connected(source, &names[index]);
+                    self.ports[source].name = names[index].clone();
+                    self.ports[source].connection = Some(connection);
+                }
+            }
+        }
+    }
+
+    /// Poll every `interval` until `stop` fires or is dropped.
+    pub fn run(mut self, stop: Receiver<()>, interval: Duration) {
+        let tick = tick(interval.unsigned_abs());
+        loop {
+            select! {
+                recv(stop) -> _ => break,
+                recv(tick) -> _ => self.poll(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_channel::unbounded;
+
+    use super::{device_name, Supervisor};
+    use crate::midi::clock::Clock;
+    use crate::{midi::input_backend::mock::MockInput, Notice};
+
+    #[test]
+    fn device_name_strips_alsa_address() {
+        assert_eq!(
+            device_name("KeyStation 88:KeyStation 88 MIDI 1 20:0"),
+            "KeyStation 88:KeyStation 88 MIDI 1"
+        );
+        assert_eq!(device_name("IAC Driver Bus 1"), "IAC Driver Bus 1");
+        assert_eq!(device_name("Port 2"), "Port 2");
+    }
+
+    #[test]
+    fn reconnect_after_replug() {
+        let backend = Arc::new(MockInput::new(vec![
+            "Midi Through:Midi Through Port-0 14:0".to_owned(),
+            "KeyStation 88:KeyStation 88 MIDI 1 20:0".to_owned(),
+        ]));
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let mut supervisor =
+            Supervisor::new(backend.clone(), midi_send, notice_send, None, Clock::new());
+        supervisor
+            .add(1, "KeyStation 88:KeyStation 88 MIDI 1 20:0")
+            .unwrap();
+        assert!(matches!(
+            notice_recv.try_recv(),
+            Ok(Notice::Connected { source: 0, .. })
+        ));
+
+        backend.set_names(vec!["Midi Through:Midi Through Port-0 14:0".to_owned()]);
+        supervisor.poll();
+        assert!(matches!(
+            notice_recv.try_recv(),
+            Ok(Notice::Disconnected { source: 0, .. })
+        ));
+        assert!(!backend.send(0, 0, &[0x90, 0x3C, 0x64]));
+
+        backend.set_names(vec![
+            "Midi Through:Midi Through Port-0 14:0".to_owned(),
+            "KeyStation 88:KeyStation 88 MIDI 1 24:0".to_owned(),
+        ]);
+        supervisor.poll();
+        match notice_recv.try_recv() {
+            Ok(Notice::Connected { source, port }) => {
+                assert_eq!(source, 0);
+                assert_eq!(port, "KeyStation 88:KeyStation 88 MIDI 1 24:0");
+            }
+            notice => panic!("unexpected {:?}", notice),
+        }
+        assert!(backend.send(1, 0, &[0x90, 0x3C, 0x64]));
+        assert_eq!(midi_recv.try_recv().unwrap().source, 0);
+
+        supervisor.poll();
+        assert!(notice_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn reconnect_after_replug_between_polls() {
+        let backend = Arc::new(MockInput::new(vec![
+            "KeyStation 88:KeyStation 88 MIDI 1 20:0".to_owned(),
+        ]));
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let mut supervisor =
+            Supervisor::new(backend.clone(), midi_send, notice_send, None, Clock::new());
+        supervisor
+            .add(0, "KeyStation 88:KeyStation 88 MIDI 1 20:0")
+            .unwrap();
+        assert!(notice_recv.try_recv().is_ok());
+
+        // Gone and back before the supervisor looked.
+        backend.set_names(vec!["KeyStation 88:KeyStation 88 MIDI 1 24:0".to_owned()]);
+        supervisor.poll();
+        assert!(matches!(
+            notice_recv.try_recv(),
+            Ok(Notice::Disconnected { source: 0, .. })
+        ));
+        assert!(matches!(
+            notice_recv.try_recv(),
+            Ok(Notice::Connected { source: 0, port }) if port.ends_with("24:0")
+        ));
+        assert!(!backend.send(0, 0, &[0x90, 0x3C, 0x64]));
+        assert!(backend.send(1, 0, &[0x90, 0x3C, 0x64]));
+        assert_eq!(midi_recv.try_recv().unwrap().source, 0);
+    }
+
+    #[test]
+    fn same_named_devices() {
+        let first = "KeyStation 88:KeyStation 88 MIDI 1 20:0".to_owned();
+        let second = "KeyStation 88:KeyStation 88 MIDI 1 24:0".to_owned();
+        let backend = Arc::new(MockInput::new(vec![first.clone(), second.clone()]));
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let mut supervisor =
+            Supervisor::new(backend.clone(), midi_send, notice_send, None, Clock::new());
+        supervisor.add(1, &second).unwrap();
+        supervisor.add(0, &first).unwrap();
+        assert_eq!(notice_recv.try_iter().count(), 2);
+
+        // Each stays on its own port.
+        supervisor.poll();
+        assert!(notice_recv.try_recv().is_err());
+
+        // The second is replugged, and not taken for the first.
+        backend.set_names(vec![
+            first.clone(),
+            "KeyStation 88:KeyStation 88 MIDI 1 28:0".to_owned(),
+        ]);
+        supervisor.poll();
+        assert!(matches!(
+            notice_recv.try_recv(),
+            Ok(Notice::Disconnected { source: 0, .. })
+        ));
+        assert!(matches!(
+            notice_recv.try_recv(),
+            Ok(Notice::Connected { source: 0, port }) if port.ends_with("28:0")
+        ));
+        assert!(notice_recv.try_recv().is_err());
+        assert!(backend.send(1, 0, &[0x90, 0x3C, 0x64]));
+        assert!(backend.send(2, 0, &[0x90, 0x3E, 0x64]));
+        let sources: Vec<usize> = midi_recv.try_iter().map(|midi| midi.source).collect();
+        assert_eq!(sources, vec![1, 0]);
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::error::{MidiError, Result};
+use midir::{MidiInput, MidiInputConnection};
+
+/// Called with the backend timestamp in microseconds and the raw message.
+pub type InputCallback = Box<dyn FnMut(u64, &[u8]) + Send>;
+
+/// An open input. Dropping it disconnects the port and drops its callback.
+pub trait InputConnection: Send {}
+
+/// Where MIDI input ports come from, so `MidiIn` can run without hardware.
+pub trait InputBackend: Send + Sync {
+    /// Names of the ports currently available, in port order.
+    fn port_names(&self) -> Result<Vec<String>>;
+    /// Connect to the port at `index` and call `callback` for every message.
+    fn connect(
+        &self,
+        index: usize,
+        name: &str,
+        callback: InputCallback,
+    ) -> Result<Box<dyn InputConnection>>;
+    /// Create a port called `name` that other applications can connect to.
+    fn create_virtual(
+        &self,
+        name: &str,
+        callback: InputCallback,
+    ) -> Result<Box<dyn InputConnection>>;
+}
+
+/// Input ports provided by midir.
+pub struct MidirInput;
+
+impl InputConnection for MidiInputConnection<()> {}
+
+impl InputBackend for MidirInput {
+    fn port_names(&self) -> Result<Vec<String>> {
+        let midi_in =
+            MidiInput::new("mirmidivi-rs").map_err(|e| MidiError::Backend(e.to_string()))?;
+        Ok(midi_in
+            .ports()
+            .iter()
+            .map(|port| midi_in.port_name(port).unwrap_or_default())
+            .collect())
+    }
+
+    fn connect(
+        &self,
+        index: usize,
+        name: &str,
+        mut callback: InputCallback,
+    ) -> Result<Box<dyn InputConnection>> {
+        let midi_in =
+            MidiInput::new("mirmidivi-rs").map_err(|e| MidiError::Backend(e.to_string()))?;
+        let in_port =
+            midi_in.ports().get(index).cloned().ok_or_else(|| {
+                MidiError::Backend(format!("MIDI input port {} disappeared", name))
+            })?;
+        let connection = midi_in
+            .connect(
+                &in_port,
+                name,
+                move |stamp, message, _| callback(stamp, message),
+                (),
+            )
+            .map_err(|e| MidiError::Backend(format!("Failed to connect to {}: {}", name, e)))?;
+        Ok(Box::new(connection))
+    }
+
+    #[cfg(unix)]
+    fn create_virtual(
+        &self,
+        name: &str,
+        mut callback: InputCallback,
+    ) -> Result<Box<dyn InputConnection>> {
+        use midir::os::unix::VirtualInput;
+
+        let midi_in =
+            MidiInput::new("mirmidivi-rs").map_err(|e| MidiError::Backend(e.to_string()))?;
+        let connection = midi_in
+            .create_virtual(name, move |stamp, message, _| callback(stamp, message), ())
+            .map_err(|e| {
+                MidiError::Backend(format!("Failed to create virtual port {}: {}", name, e))
+            })?;
+        Ok(Box::new(connection))
+    }
+
+    #[cfg(not(unix))]
+    fn create_virtual(
+        &self,
+        _name: &str,
+        _callback: InputCallback,
+    ) -> Result<Box<dyn InputConnection>> {
+        Err(MidiError::Backend(
+            "Virtual MIDI ports are not supported on this platform".to_owned(),
+        ))
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::sync::{Arc, Mutex};
+
+    use crate::midi::error::{MidiError, Result};
+
+    use super::{InputBackend, InputCallback, InputConnection};
+
+    type Callbacks = Arc<Mutex<Vec<Option<InputCallback>>>>;
+
+    /// Backend whose ports are fed by the test through `send`.
+    pub struct MockInput {
+        names: Mutex<Vec<String>>,
+        callbacks: Callbacks,
+    }
+
+    struct MockConnection {
+        callbacks: Callbacks,
+        slot: usize,
+    }
+
+    impl InputConnection for MockConnection {}
+
+    impl Drop for MockConnection {
+        fn drop(&mut self) {
+            self.callbacks.lock().unwrap()[self.slot] = None;
+        }
+    }
+
+    impl MockInput {
+        pub fn new(names: Vec<String>) -> Self {
+            Self {
+                names: Mutex::new(names),
+                callbacks: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        /// Plug and unplug ports.
+        pub fn set_names(&self, names: Vec<String>) {
+            *self.names.lock().unwrap() = names;
+        }
+
+        /// Deliver `message` on the `slot`th connection, if it is still open.
+        pub fn send(&self, slot: usize, stamp: u64, message: &[u8]) -> bool {
+            match &mut self.callbacks.lock().unwrap()[slot] {
+                Some(callback) => {
+                    callback(stamp, message);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    impl InputBackend for MockInput {
+        fn port_names(&self) -> Result<Vec<String>> {
+            Ok(self.names.lock().unwrap().clone())
+        }
+
+        fn connect(
+            &self,
+            index: usize,
+            _name: &str,
+            callback: InputCallback,
+        ) -> Result<Box<dyn InputConnection>> {
+            if index >= self.names.lock().unwrap().len() {
+                return Err(MidiError::Backend("no such port".to_owned()));
+            }
+            self.create_virtual("", callback)
+        }
+
+        fn create_virtual(
+            &self,
+            _name: &str,
+            callback: InputCallback,
+        ) -> Result<Box<dyn InputConnection>> {
+            let mut callbacks = self.callbacks.lock().unwrap();
+            callbacks.push(Some(callback));
+            Ok(Box::new(MockConnection {
+                callbacks: self.callbacks.clone(),
+                slot: callbacks.len() - 1,
+            }))
+        }
+    }
+}
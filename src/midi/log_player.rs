@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+
+use super::clock::Clock;
+use super::error::Result;
+use super::event_log::EventLog;
+use super::midi_player::read_file;
+use super::mixer::Mixer;
+use super::playback::Transport;
+use super::recorder::Recorder;
+use super::song::Score;
+use super::thru::Thru;
+use crate::{midi::MidiProvider, options::Options, MidiData, Notice};
+
+/// Replays an event log written with `--log`, each message at the time
+/// it was logged and otherwise exactly as it was.
+pub struct LogPlayer {
+    midi_recv: Receiver<MidiData>,
+    notice_recv: Receiver<Notice>,
+    clock: Clock,
+    /// Dropped to stop replaying
+    stop_send: Option<Sender<()>>,
+    handler: Option<JoinHandle<()>>,
+}
+
+impl MidiProvider for LogPlayer {
+    fn get_midi_in_recv(&self) -> Receiver<MidiData> {
+        self.midi_recv.clone()
+    }
+
+    fn get_notice_recv(&self) -> Receiver<Notice> {
+        self.notice_recv.clone()
+    }
+
+    fn get_clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    fn get_transport(&self) -> Option<Transport> {
+        None
+    }
+
+    fn get_recorder(&self) -> Option<Recorder> {
+        None
+    }
+
+    fn get_mixer(&self) -> Option<Mixer> {
+        None
+    }
+
+    fn get_score(&self) -> Option<Arc<Score>> {
+        None
+    }
+
+    fn new(opts: &Options) -> Result<Self> {
+        let path = opts.replay.as_deref().unwrap_or_default();
+        let log = read_file(path)?;
+        let events = EventLog::parse(path, &String::from_utf8_lossy(&log))?;
+        let thru = Thru::from_options(opts)?;
+        Ok(Self::with_events(events, thru))
+    }
+}
+
+impl LogPlayer {
+    fn with_events(events: Vec<MidiData>, thru: Option<Thru>) -> Self {
+        let (midi_send, midi_recv) = unbounded();
+        // Nothing to say besides the messages, but renderers still listen.
+        let (_notice_send, notice_recv) = unbounded();
+        let clock = Clock::new();
+        let (stop_send, stop_recv) = bounded(0);
+
+        let replay_clock = clock.clone();
+        let handler = thread::spawn(move || {
+            for midi in events {
+                let wait = midi.timestamp - replay_clock.now();
+                if wait.is_positive() {
+                    match stop_recv.recv_timeout(wait.unsigned_abs()) {
+                        Err(RecvTimeoutError::Timeout) => (),
+                        _ => return,
+                    }
+                }
+                if let Some(thru) = &thru {
+                    thru.send(&midi.message);
+                }
+                let _send = midi_send.send(midi);
+            }
+        });
+
+        LogPlayer {
+            midi_recv,
+            notice_recv,
+            clock,
+            stop_send: Some(stop_send),
+            handler: Some(handler),
+        }
+    }
+
+    /// Stop replaying.
+    ///
+    /// Receivers see the channel disconnect once the pending messages are
+    /// read, as they do when the whole log has been replayed.
+    pub fn close(&mut self) {
+        self.stop_send.take();
+        if let Some(handler) = self.handler.take() {
+            let _ = handler.join();
+        }
+    }
+}
+
+impl Drop for LogPlayer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration as StdDuration};
+
+    use clap::Parser;
+    use crossbeam_channel::RecvTimeoutError;
+    use time::Duration;
+
+    use super::LogPlayer;
+    use crate::midi::test_util::TempPath;
+    use crate::midi::{MidiError, MidiProvider};
+    use crate::Options;
+
+    #[test]
+    fn log_player_replays() {
+        let path = TempPath::new(".txt");
+        fs::write(
+            &path.0,
+            "# mirmidivi-rs event log 1\n100000 1 99 24 7f\n100000 0 f8\n300000 0 89 24 00\n",
+        )
+        .unwrap();
+        let opts = Options::parse_from(["mirmidivi-rs", "--replay", path.to_str()]);
+        let midi = LogPlayer::new(&opts).unwrap();
+        let midi_recv = midi.get_midi_in_recv();
+        let clock = midi.get_clock();
+
+        let replayed: Vec<_> = midi_recv
+            .iter()
+            .map(|midi| {
+                // Not early, and as it was logged.
+                assert!(clock.now() >= midi.timestamp);
+                (midi.timestamp, midi.source, midi.message)
+            })
+            .collect();
+        assert_eq!(
+            replayed,
+            vec![
+                (Duration::milliseconds(100), 1, vec![0x99, 0x24, 0x7F]),
+                (Duration::milliseconds(100), 0, vec![0xF8]),
+                (Duration::milliseconds(300), 0, vec![0x89, 0x24, 0x00]),
+            ]
+        );
+        fs::remove_file(&path.0).unwrap();
+
+        let opts = Options::parse_from(["mirmidivi-rs", "--replay", path.to_str()]);
+        assert!(matches!(
+            LogPlayer::new(&opts),
+            Err(MidiError::FileNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn log_player_close() {
+        let path = TempPath::new(".txt");
+        fs::write(
+            &path.0,
+            "# mirmidivi-rs event log 1\n0 0 90 3c 64\n60000000 0 80 3c 00\n",
+        )
+        .unwrap();
+        let opts = Options::parse_from(["mirmidivi-rs", "--replay", path.to_str()]);
+        let mut midi = LogPlayer::new(&opts).unwrap();
+        fs::remove_file(&path.0).unwrap();
+        let midi_recv = midi.get_midi_in_recv();
+
+        let first = midi_recv.recv_timeout(StdDuration::from_secs(1)).unwrap();
+        assert_eq!(first.message, vec![0x90, 0x3C, 0x64]);
+        midi.close();
+        assert!(matches!(
+            midi_recv.recv_timeout(StdDuration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+}
@@ -1,17 +1,43 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
-use std::time::Instant;
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
 use time::Duration;
 
-use crossbeam_channel::{unbounded, Receiver};
-use midir::{MidiInput, MidiInputConnection};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
 
-use crate::{midi::MidiProvider, options::Options, MidiData};
+use super::clock::Clock;
+use super::error::Result;
+use super::event_log::EventLog;
+use super::hotplug::Supervisor;
+use super::input_backend::{InputBackend, MidirInput};
+use super::mixer::Mixer;
+use super::playback::Transport;
+use super::ports::select_ports;
+use super::recorder::Recorder;
+use super::song::Score;
+use super::thru::Thru;
+use crate::{midi::MidiProvider, options::Options, MidiData, Notice};
+
+/// How often the port list is checked for unplugged and replugged devices.
+const POLL_INTERVAL: Duration = Duration::seconds(1);
 
 /// Midi Input
 pub struct MidiIn {
     /// Midi Input connection
     midi_recv: Receiver<MidiData>,
-    epoch: Instant,
+    notice_recv: Receiver<Notice>,
+    clock: Clock,
+    /// Dropped to stop the supervisor, which owns the connections
+    stop_send: Option<Sender<()>>,
+    supervisor: Option<JoinHandle<()>>,
+    recorder: Recorder,
+    /// Mutes and solos channels
+    mixer: Mixer,
+    /// Pass the messages on to `midi_recv`; see [`Recorder::tap`] and
+    /// [`EventLog::tap`]
+    taps: Vec<JoinHandle<()>>,
 }
 
 impl MidiProvider for MidiIn {
@@ -19,51 +45,203 @@ impl MidiProvider for MidiIn {
         self.midi_recv.clone()
     }
 
-    fn get_epoch(&self) -> Instant {
-        self.epoch
+    fn get_notice_recv(&self) -> Receiver<Notice> {
+        self.notice_recv.clone()
+    }
+
+    fn get_clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    fn get_transport(&self) -> Option<Transport> {
+        None
+    }
+
+    fn get_recorder(&self) -> Option<Recorder> {
+        Some(self.recorder.clone())
+    }
+
+    fn get_mixer(&self) -> Option<Mixer> {
+        Some(self.mixer.clone())
     }
 
-    fn new(opts: &Options) -> Self {
-        let midi_in = MidiInput::new("mirmidivi-rs").unwrap();
-        let in_ports = midi_in.ports();
-        let in_port = &in_ports[0];
+    fn get_score(&self) -> Option<Arc<Score>> {
+        None
+    }
+
+    fn new(opts: &Options) -> Result<Self> {
+        Self::with_backend(opts, Arc::new(MidirInput))
+    }
+}
 
-        let in_port_name = midi_in.port_name(in_port).unwrap();
+impl MidiIn {
+    /// Connect to the ports selected by `opts` through `backend`, and keep
+    /// them connected while devices come and go.
+    fn with_backend(opts: &Options, backend: Arc<dyn InputBackend>) -> Result<Self> {
+        let names = backend.port_names()?;
+        // A virtual port is enough on its own; only fall back to the first port without one.
+        let indices = match (&opts.virtual_port, opts.port.is_empty()) {
+            (Some(_), true) => Vec::new(),
+            _ => select_ports("input", &names, &opts.port)?,
+        };
 
         let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let clock = Clock::new();
+        let mixer = Mixer::new();
+        let (recorder, midi_recv, taps) = tap(opts, &clock, &notice_send, &mixer, midi_recv)?;
+
+        let thru = Thru::from_options(opts)?.map(|thru| thru.with_mixer(mixer.clone()));
+
+        let mut supervisor = Supervisor::new(backend, midi_send, notice_send, thru, clock.clone());
+        for index in indices {
+            supervisor.add(index, &names[index])?;
+        }
+        if let Some(name) = &opts.virtual_port {
+            supervisor.add_virtual(name)?;
+        }
 
-        let _ = midi_in.connect(
-            in_port,
-            &in_port_name,
-            move |stamp, message, _| {
-                let _send = midi_send.send(MidiData {
-                    message: message.to_vec(),
-                    timestamp: Duration::microseconds(stamp as i64),
-                });
-            },
-            (),
-        );
-
-        MidiIn {
+        let (stop_send, stop_recv) = bounded(0);
+        let supervisor = thread::spawn(move || supervisor.run(stop_recv, POLL_INTERVAL));
+
+        Ok(MidiIn {
             midi_recv,
-            epoch: Instant::now(),
+            notice_recv,
+            clock,
+            stop_send: Some(stop_send),
+            supervisor: Some(supervisor),
+            recorder,
+            mixer,
+            taps,
+        })
+    }
+
+    /// Disconnect every port, and write what is being recorded or logged.
+    ///
+    /// Receivers see the channel disconnect once the pending messages are read.
+    pub fn close(&mut self) {
+        self.stop_send.take();
+        if let Some(supervisor) = self.supervisor.take() {
+            let _ = supervisor.join();
         }
+        self.taps.drain(..).for_each(|tap| {
+            let _ = tap.join();
+        });
+    }
+}
+
+/// Record and log the live messages from `midi_recv` as `opts` say, then
+/// hold back those `mixer` mutes.
+///
+/// Returns the recorder, the receiver the messages are passed on to, and
+/// the threads passing them, which end once `midi_recv` disconnects.
+pub(super) fn tap(
+    opts: &Options,
+    clock: &Clock,
+    notice_send: &Sender<Notice>,
+    mixer: &Mixer,
+    midi_recv: Receiver<MidiData>,
+) -> Result<(Recorder, Receiver<MidiData>, Vec<JoinHandle<()>>)> {
+    let recorder = Recorder::from_options(opts, clock.clone(), notice_send.clone());
+    let (midi_recv, tap) = recorder.tap(midi_recv);
+    let mut taps = vec![tap];
+    let midi_recv = match &opts.log {
+        Some(path) => {
+            let (midi_recv, tap) = EventLog::tap(path, midi_recv, notice_send.clone())?;
+            taps.push(tap);
+            midi_recv
+        }
+        None => midi_recv,
+    };
+    let (midi_recv, tap) = mixer.tap(midi_recv);
+    taps.push(tap);
+    Ok((recorder, midi_recv, taps))
+}
+
+impl Drop for MidiIn {
+    fn drop(&mut self) {
+        self.close();
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use crossbeam_channel::RecvTimeoutError;
+
     use crate::midi::MidiProvider;
 
-    use crate::midi::MidiIn;
+    use crate::midi::input_backend::mock::MockInput;
+    use crate::midi::{MidiIn, Part};
     use crate::Options;
 
+    fn port_names() -> Vec<String> {
+        vec![
+            "Midi Through:Midi Through Port-0 14:0".to_owned(),
+            "KeyStation 88:KeyStation 88 MIDI 1 20:0".to_owned(),
+            "nanoPAD2:nanoPAD2 MIDI 1 24:0".to_owned(),
+        ]
+    }
+
     #[test]
     fn add_on_event_instance() {
-        let opts: Options = Options {
-            renderer: "text".to_owned(),
-            midifile: None,
-        };
-        let midi_in = MidiIn::new(&opts);
+        let opts: Options = Options::parse_from(["mirmidivi-rs"]);
+        let _midi_in = MidiIn::new(&opts).unwrap();
+    }
+
+    #[test]
+    fn events_flow_after_new() {
+        let backend = Arc::new(MockInput::new(port_names()));
+        let opts = Options::parse_from(["mirmidivi-rs", "-p", "keystation", "-p", "nanopad"]);
+        let mut midi_in = MidiIn::with_backend(&opts, backend.clone()).unwrap();
+        let midi_recv = midi_in.get_midi_in_recv();
+
+        assert!(backend.send(0, 1000, &[0x90, 0x3C, 0x64]));
+        assert!(backend.send(1, 2000, &[0x99, 0x24, 0x7F]));
+
+        let first = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(first.message, vec![0x90, 0x3C, 0x64]);
+        assert_eq!(first.source, 0);
+        let second = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(second.message, vec![0x99, 0x24, 0x7F]);
+        assert_eq!(second.source, 1);
+        assert!(second.timestamp >= time::Duration::microseconds(2000));
+
+        midi_in.close();
+        assert!(!backend.send(0, 3000, &[0x80, 0x3C, 0x00]));
+        assert!(matches!(
+            midi_recv.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn muted_channels_held_back() {
+        let backend = Arc::new(MockInput::new(port_names()));
+        let opts = Options::parse_from(["mirmidivi-rs", "-p", "keystation"]);
+        let midi_in = MidiIn::with_backend(&opts, backend.clone()).unwrap();
+        let midi_recv = midi_in.get_midi_in_recv();
+
+        midi_in.get_mixer().unwrap().toggle_mute(Part::Channel(9));
+        assert!(backend.send(0, 1000, &[0x99, 0x24, 0x7F]));
+        assert!(backend.send(0, 2000, &[0x90, 0x3C, 0x64]));
+        let data = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(data.message, vec![0x90, 0x3C, 0x64]);
+    }
+
+    #[test]
+    fn virtual_port_without_hardware() {
+        let backend = Arc::new(MockInput::new(Vec::new()));
+        let opts = Options::parse_from(["mirmidivi-rs", "--virtual-port", "mirmidivi"]);
+        let midi_in = MidiIn::with_backend(&opts, backend.clone()).unwrap();
+        let midi_recv = midi_in.get_midi_in_recv();
+
+        assert!(backend.send(0, 1000, &[0x90, 0x3C, 0x64]));
+        let data = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(data.message, vec![0x90, 0x3C, 0x64]);
+        assert_eq!(data.source, 0);
     }
 }
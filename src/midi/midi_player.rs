@@ -1,31 +1,33 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::thread;
-use std::time::Instant;
-use std::{fs, sync::mpsc};
-
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use nodi::midly::Timing;
-use nodi::timers::TimeFormatError;
-use nodi::{
-    self,
-    midly::{Format, Smf},
-    timers::{ControlTicker, Ticker},
-    Connection, MidiEvent, Player, Sheet,
-};
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::{fs, io};
+
+use crossbeam_channel::{unbounded, Receiver};
+use nodi::midly::{Fps, Smf, Timing};
 use time::Duration;
 
-use crate::{midi::MidiProvider, options::Options, MidiData};
+use super::clock::Clock;
+use super::error::{MidiError, Result};
+use super::mixer::Mixer;
+use super::output::Output;
+use super::playback::{Playback, Transport};
+use super::playlist::Playlist;
+use super::recorder::Recorder;
+use super::song::{Score, Song};
+use super::thru::Thru;
+use crate::{midi::MidiProvider, options::Options, MidiData, Notice};
 
 pub struct MidiPlayer {
-    epoch: Instant,
-    pause_send: mpsc::Sender<()>,
+    clock: Clock,
     midi_recv: Receiver<MidiData>,
-}
-
-struct MidiPlayerConnection {
-    epoch: Instant,
-    midi_send: Sender<MidiData>,
+    notice_recv: Receiver<Notice>,
+    transport: Transport,
+    score: Arc<Score>,
+    /// Plays the song; see [`Playback`]
+    playback: Option<JoinHandle<()>>,
 }
 
 impl MidiProvider for MidiPlayer {
@@ -33,88 +35,609 @@ impl MidiProvider for MidiPlayer {
         self.midi_recv.clone()
     }
 
-    fn get_epoch(&self) -> Instant {
-        self.epoch
+    fn get_notice_recv(&self) -> Receiver<Notice> {
+        self.notice_recv.clone()
+    }
+
+    fn get_clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    fn get_transport(&self) -> Option<Transport> {
+        Some(self.transport.clone())
+    }
+
+    fn get_recorder(&self) -> Option<Recorder> {
+        None
+    }
+
+    fn get_mixer(&self) -> Option<Mixer> {
+        Some(self.transport.mixer().clone())
+    }
+
+    fn get_score(&self) -> Option<Arc<Score>> {
+        Some(self.score.clone())
     }
 
-    fn new(opts: &Options) -> Self {
+    fn new(opts: &Options) -> Result<Self> {
         let (midi_send, midi_recv) = unbounded();
-        let epoch = Instant::now();
-        let file = fs::read(&opts.midifile.clone().unwrap()).expect("Failed to open MIDI file");
-        let Smf { header, tracks } = Smf::parse(&file).expect("Failed to parse MIDI file");
-        let sheet = match header.format {
-            Format::SingleTrack | Format::Sequential => Sheet::sequential(&tracks),
-            Format::Parallel => Sheet::parallel(&tracks),
+        let (notice_send, notice_recv) = unbounded();
+        let mut playlist = Playlist::from_options(opts)?;
+        // Files that will not load are skipped, unless none will.
+        let (song, mut skipped) = playlist.load_from(0, true);
+        let Some(song) = song else {
+            return Err(skipped.remove(0).1);
         };
-        let (pause_send, pause_recv) = mpsc::channel();
-        let t = match header.timing {
-            Timing::Metrical(n) => Ok(n),
-            _ => Err(TimeFormatError),
-        };
-        let timer = ControlTicker::new(t.unwrap().into(), pause_recv);
-        let mut player = Player::new(timer, MidiPlayerConnection::new(midi_send, epoch));
-        thread::spawn(move || player.play(&sheet));
+        let score = song.score();
+        let output = Thru::from_options(opts)?.map(|thru| Output::new(Box::new(thru)));
+        let clock = Clock::new();
+        let (playback, transport) = Playback::new(
+            song,
+            playlist,
+            clock.clone(),
+            midi_send,
+            notice_send,
+            output,
+        );
+        transport.set_speed(opts.speed);
+        if opts.loop_start.is_some() || opts.loop_end.is_some() {
+            let (start, end) = (opts.loop_start.as_deref(), opts.loop_end.as_deref());
+            transport.set_loop(loop_range(&transport.song(), start, end)?);
+        }
+        let playback = thread::spawn(move || playback.run(skipped));
 
-        MidiPlayer {
-            epoch,
-            pause_send,
+        Ok(MidiPlayer {
+            clock,
             midi_recv,
+            notice_recv,
+            transport,
+            score,
+            playback: Some(playback),
+        })
+    }
+}
+
+/// Print the sequences of the (first) MIDI file as tab separated `number
+/// length name` lines, numbered as `--sequence` takes them.
+pub fn list_sequences(opts: &Options) -> Result<()> {
+    let path = opts.midifile.first().map_or("", String::as_str);
+    let file = read_file(path)?;
+    Song::sequences(&parse_file(path, &file)?)
+        .iter()
+        .enumerate()
+        .for_each(|(index, sequence)| {
+            println!(
+                "{}\t{:.3}\t{}",
+                index + 1,
+                sequence.length.as_seconds_f64(),
+                sequence.name
+            );
+        });
+    Ok(())
+}
+
+/// Resolve loop points given as for [`Song::position`], defaulting to the
+/// whole song. The loop has to end after it starts.
+fn loop_range(
+    song: &Song,
+    start_spec: Option<&str>,
+    end_spec: Option<&str>,
+) -> Result<Range<Duration>> {
+    let start = match start_spec {
+        Some(start) => song.position(start, false)?,
+        None => Duration::ZERO,
+    };
+    let end = match end_spec {
+        Some(end) => song.position(end, true)?,
+        None => song.length(),
+    };
+    if start >= end {
+        return Err(MidiError::Position {
+            spec: end_spec.or(start_spec).unwrap_or_default().to_owned(),
+            reason: "the loop would end before it starts".to_owned(),
+        });
+    }
+    Ok(start..end)
+}
+
+pub(super) fn read_file(path: &str) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|source| match source.kind() {
+        io::ErrorKind::NotFound => MidiError::FileNotFound {
+            path: path.to_owned(),
+        },
+        _ => MidiError::Io {
+            path: path.to_owned(),
+            source,
+        },
+    })
+}
+
+pub(super) fn parse_file<'a>(path: &str, raw: &'a [u8]) -> Result<Smf<'a>> {
+    let unsupported = |timing: String| MidiError::UnsupportedTiming {
+        path: path.to_owned(),
+        timing,
+    };
+    let smf = Smf::parse(raw).map_err(|e| match smpte_fps(raw) {
+        // midly only reads the frame rates SMPTE has.
+        Some(fps) if Fps::from_int(fps).is_none() => {
+            unsupported(format!("{} frames per second", fps))
         }
+        _ => MidiError::Parse {
+            path: path.to_owned(),
+            offset: error_offset(raw),
+            reason: e.to_string(),
+        },
+    })?;
+    match smf.header.timing {
+        Timing::Metrical(ticks) if ticks == 0 => Err(unsupported("0 ticks per beat".to_owned())),
+        Timing::Timecode(_, 0) => Err(unsupported("0 ticks per frame".to_owned())),
+        _ => Ok(smf),
     }
 }
 
-impl MidiPlayer {
-    pub fn toggle_pause_resume(&mut self) {
-        let _ = self.pause_send.send(());
+/// Where `raw` stops being a Standard MIDI File.
+///
+/// midly skips over broken tracks, so only the header chunk can be at fault:
+/// its chunk id, its length, or the first of its fields that is cut short or
+/// makes no sense, as far as the length lets it go.
+fn error_offset(raw: &[u8]) -> usize {
+    let length = match (raw.get(..4), raw.get(4..8)) {
+        (Some(b"MThd"), Some(length)) => u32::from_be_bytes(length.try_into().unwrap()),
+        (Some(b"MThd"), None) => return 4,
+        _ => return 0,
+    };
+    let header = &raw[..raw.len().min(8 + length as usize)];
+    let field = |offset: usize| {
+        header
+            .get(offset..offset + 2)
+            .map(|field| u16::from_be_bytes([field[0], field[1]]))
+    };
+    match (field(8), field(10)) {
+        // Format 0, 1 or 2
+        (None, _) | (Some(3..), _) => 8,
+        (_, None) => 10,
+        // The timing, the only field left
+        _ => 12,
     }
 }
 
-impl MidiPlayerConnection {
-    pub fn new(send: Sender<MidiData>, epoch: Instant) -> Self {
-        Self {
-            midi_send: send,
-            epoch,
+/// The frame rate the header of `raw` gives, if it counts time in SMPTE
+/// frames rather than beats.
+fn smpte_fps(raw: &[u8]) -> Option<u8> {
+    let division = raw.strip_prefix(b"MThd")?.get(8)?;
+    (division & 0x80 != 0).then(|| (*division as i8).unsigned_abs())
+}
+
+impl MidiPlayer {
+    /// Stop playback for good, releasing every note on the output.
+    pub fn close(&mut self) {
+        self.transport.close();
+        if let Some(playback) = self.playback.take() {
+            let _ = playback.join();
         }
     }
 }
 
-impl Connection for MidiPlayerConnection {
-    fn play(&mut self, event: MidiEvent) -> bool {
-        let mut message = Vec::with_capacity(8);
-        event.write(&mut message).unwrap();
-        let _send = self.midi_send.send(MidiData {
-            message,
-            timestamp: Duration::try_from(self.epoch.elapsed()).unwrap(),
-        });
-        true
+impl Drop for MidiPlayer {
+    fn drop(&mut self) {
+        self.close();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::midi::MidiProvider;
-    use std::time::Instant;
+    use clap::Parser;
+    use crossbeam_channel::Receiver;
+    use nodi::midly::{
+        Format, Fps, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    };
+    use std::time::Duration;
 
     use crate::options::Options;
 
     use super::MidiPlayer;
+    use crate::midi::test_util::TempPath;
+    use crate::midi::Meta;
+    use crate::midi::MidiError;
+    use crate::midi::Part;
+    use crate::Notice;
+
+    /// A file of `tracks` timed by SMPTE timecode, 25 fps with 40 subframes,
+    /// so a tick is a millisecond.
+    fn timecode_smf(format: Format, tracks: Vec<Vec<TrackEvent<'static>>>) -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(format, Timing::Timecode(Fps::Fps25, 40)));
+        smf.tracks = tracks;
+        smf
+    }
+
+    fn note(delta: u32, message: MidiMessage) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message,
+            },
+        }
+    }
+
+    fn note_on(key: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: 100.into(),
+        }
+    }
+
+    fn note_off(key: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 0.into(),
+        }
+    }
 
     #[test]
     fn midi_player() {
-        let opts: Options = Options {
-            renderer: "text".to_owned(),
-            midifile: Some("sample.mid".to_owned()),
-        };
-        let _midi_player = MidiPlayer::new(&opts);
+        let opts: Options = Options::parse_from(["mirmidivi-rs", "--midifile", "sample.mid"]);
+        let _midi_player = MidiPlayer::new(&opts).unwrap();
     }
 
     #[test]
-    #[should_panic]
     fn midi_player_not_exist_file() {
-        let opts: Options = Options {
-            renderer: "text".to_owned(),
-            midifile: Some("/not/exist/file.mid".to_owned()),
+        let opts: Options =
+            Options::parse_from(["mirmidivi-rs", "--midifile", "/not/exist/file.mid"]);
+        assert!(matches!(
+            MidiPlayer::new(&opts),
+            Err(MidiError::FileNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn midi_player_broken_file() {
+        let header = b"MThd\x00\x00\x00\x06\x00\x01\x00\x01\x01\xE0";
+        [
+            // Format 3 does not exist.
+            (&b"MThd\x00\x00\x00\x06\x00\x03\x00\x01\x01\xE0"[..], 8),
+            // Cut short in the length, the track count and the timing.
+            (&header[..6], 4),
+            (&header[..11], 10),
+            (&header[..13], 12),
+            (b"RIFF", 0),
+        ]
+        .iter()
+        .for_each(|(file, at)| {
+            let path = TempPath::with_bytes(file);
+            match MidiPlayer::new(&path.opts(&[])) {
+                Err(MidiError::Parse { offset, .. }) => assert_eq!(offset, *at),
+                Err(e) => panic!("unexpected {}", e),
+                Ok(_) => panic!("parsed a broken file"),
+            }
+        });
+
+        // 28 frames per second, then 0 ticks per beat.
+        [b"\xE4\x28", b"\x00\x00"].iter().for_each(|division| {
+            let mut file = b"MThd\x00\x00\x00\x06\x00\x01\x00\x00".to_vec();
+            file.extend_from_slice(*division);
+            let path = TempPath::with_bytes(&file);
+            assert!(matches!(
+                MidiPlayer::new(&path.opts(&[])),
+                Err(MidiError::UnsupportedTiming { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn midi_player_timecode() {
+        let path = TempPath::with_smf(&timecode_smf(
+            Format::SingleTrack,
+            vec![vec![
+                TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::Tempo(250_000.into())),
+                },
+                note(0, note_on(60)),
+                note(200, note_off(60)),
+                TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+                },
+            ]],
+        ));
+        let midi_player = path.play(&[]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let note_on = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        let note_off = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(note_on.message, vec![0x90, 60, 100]);
+        assert_eq!(note_off.message, vec![0x80, 60, 0]);
+        let length = note_off.timestamp - note_on.timestamp;
+        assert!(length >= time::Duration::milliseconds(200));
+        assert!(length < time::Duration::milliseconds(300));
+    }
+
+    #[test]
+    fn midi_player_seek() {
+        let path = TempPath::with_smf(&timecode_smf(
+            Format::SingleTrack,
+            vec![vec![
+                note(0, MidiMessage::ProgramChange { program: 5.into() }),
+                note(0, note_on(60)),
+                note(5000, note_off(60)),
+            ]],
+        ));
+        let midi_player = path.play(&[]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let notice_recv = midi_player.get_notice_recv();
+        let recv = || {
+            let midi = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+            (midi.message, midi.timestamp)
+        };
+        let seeked = || seeked(&notice_recv);
+        assert_eq!(recv(), (vec![0xC0, 5], time::Duration::ZERO));
+        assert_eq!(recv(), (vec![0x90, 60, 100], time::Duration::ZERO));
+
+        midi_player.transport.pause();
+        midi_player.transport.seek(time::Duration::seconds(3));
+        // The sounding note ends where playback left, and is drawn anew from
+        // where it lands, with the program it was played with.
+        let (message, _) = recv();
+        assert_eq!(message, vec![0x80, 60, 0]);
+        let at = time::Duration::seconds(3);
+        assert_eq!(seeked(), at);
+        assert_eq!(recv(), (vec![0xC0, 5], at));
+        assert_eq!(recv(), (vec![0x90, 60, 100], at));
+        assert_eq!(midi_player.get_clock().now(), at);
+        assert!(midi_recv.recv_timeout(Duration::from_millis(100)).is_err());
+
+        midi_player.transport.seek_to_bar(3);
+        assert_eq!(recv().0, vec![0x80, 60, 0]);
+        assert_eq!(seeked(), time::Duration::seconds(4));
+    }
+
+    /// Where the next seek landed, passing over other notices.
+    fn seeked(notice_recv: &Receiver<Notice>) -> time::Duration {
+        loop {
+            if let Notice::Seeked { position } =
+                notice_recv.recv_timeout(Duration::from_secs(1)).unwrap()
+            {
+                break position;
+            }
+        }
+    }
+
+    #[test]
+    fn midi_player_loop() {
+        let path = TempPath::with_smf(&timecode_smf(
+            Format::SingleTrack,
+            vec![vec![note(100, note_on(60)), note(1000, note_off(60))]],
+        ));
+        let midi_player = path.play(&["--loop-start", "0.05", "--loop-end", "0.3"]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let notice_recv = midi_player.get_notice_recv();
+        let recv = || {
+            let midi = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+            (midi.message, midi.timestamp)
+        };
+        let start = time::Duration::milliseconds(50);
+        let on = time::Duration::milliseconds(100);
+        for _ in 0..2 {
+            assert_eq!(recv(), (vec![0x90, 60, 100], on));
+            // Released at the end of the loop, never reaching the note off.
+            let (message, timestamp) = recv();
+            assert_eq!(message, vec![0x80, 60, 0]);
+            assert!(timestamp >= time::Duration::milliseconds(300));
+            assert_eq!(seeked(&notice_recv), start);
+        }
+
+        // A loop set behind the playhead starts over at once.
+        let midi_player = path.play(&[]);
+        let notice_recv = midi_player.get_notice_recv();
+        midi_player.transport.pause();
+        midi_player
+            .transport
+            .seek(time::Duration::milliseconds(900));
+        assert_eq!(seeked(&notice_recv), time::Duration::milliseconds(900));
+        midi_player
+            .transport
+            .set_loop(start..time::Duration::milliseconds(300));
+        assert_eq!(seeked(&notice_recv), start);
+        assert_eq!(midi_player.get_clock().now(), start);
+
+        opts_error(&["--loop-start", "bar:9999"]);
+        opts_error(&["--loop-end", "marker:Coda"]);
+        opts_error(&["--loop-start", "2", "--loop-end", "1"]);
+        opts_error(&["--loop-start", "1:00", "--loop-end", "60"]);
+    }
+
+    fn opts_error(args: &[&str]) {
+        let opts: Options = Options::parse_from(
+            ["mirmidivi-rs", "--midifile", "sample.mid"]
+                .iter()
+                .chain(args),
+        );
+        assert!(matches!(
+            MidiPlayer::new(&opts),
+            Err(MidiError::Position { .. })
+        ));
+    }
+
+    #[test]
+    fn midi_player_speed() {
+        let path = TempPath::with_smf(&timecode_smf(
+            Format::SingleTrack,
+            vec![vec![note(0, note_on(60)), note(400, note_off(60))]],
+        ));
+        let midi_player = path.play(&["--speed", "2"]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let note_on = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        let begin = std::time::Instant::now();
+        let note_off = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        // Twice as fast, but still stamped in song time.
+        assert!(begin.elapsed() < Duration::from_millis(300));
+        assert_eq!(note_on.timestamp, time::Duration::ZERO);
+        assert_eq!(note_off.timestamp, time::Duration::milliseconds(400));
+
+        assert!(Options::try_parse_from(["mirmidivi-rs", "--speed", "3"]).is_err());
+    }
+
+    #[test]
+    fn midi_player_mute() {
+        let mut accompaniment = vec![
+            note(0, note_on(64)),
+            note(200, note_on(67)),
+            note(200, note_off(64)),
+            note(0, note_off(67)),
+        ];
+        accompaniment.iter_mut().for_each(|event| {
+            if let TrackEventKind::Midi { channel, .. } = &mut event.kind {
+                *channel = 1.into();
+            }
+        });
+        let path = TempPath::with_smf(&timecode_smf(
+            Format::Parallel,
+            vec![
+                vec![note(0, note_on(60)), note(400, note_off(60))],
+                accompaniment,
+            ],
+        ));
+        let midi_player = path.play(&[]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let recv = || {
+            midi_recv
+                .recv_timeout(Duration::from_millis(500))
+                .ok()
+                .map(|midi| (midi.message, midi.timestamp))
         };
-        let _midi_player = MidiPlayer::new(&opts);
+        assert_eq!(recv().unwrap().0, vec![0x90, 60, 100]);
+        assert_eq!(recv().unwrap().0, vec![0x91, 64, 100]);
+
+        // The sounding note ends, the rest of the track is never played.
+        midi_player.transport.toggle_mute(Part::Track(1));
+        assert_eq!(recv().unwrap().0, vec![0x81, 64, 0]);
+        assert_eq!(
+            recv(),
+            Some((vec![0x80, 60, 0], time::Duration::milliseconds(400)))
+        );
+        assert_eq!(recv(), None);
+    }
+
+    #[test]
+    fn midi_player_sequences() {
+        let path = TempPath::with_smf(&timecode_smf(
+            Format::Sequential,
+            vec![vec![note(100, note_on(60))], vec![note(100, note_on(64))]],
+        ));
+        let midi_player = path.play(&["--sequence", "2,1"]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let recv = || midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        let (first, second) = (recv(), recv());
+        assert_eq!(first.message, vec![0x90, 64, 100]);
+        assert_eq!(first.timestamp, time::Duration::milliseconds(100));
+        assert_eq!(second.message, vec![0x90, 60, 100]);
+        assert_eq!(second.timestamp, time::Duration::milliseconds(200));
+
+        assert!(matches!(
+            MidiPlayer::new(&path.opts(&["--sequence", "1,3"])),
+            Err(MidiError::Sequence {
+                sequence: 3,
+                count: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn midi_player_playlist() {
+        let dir = TempPath::dir();
+        [(1, 60), (3, 64)].iter().for_each(|&(number, key)| {
+            let smf = timecode_smf(Format::SingleTrack, vec![vec![note(100, note_on(key))]]);
+            smf.save(dir.0.join(format!("{}.mid", number))).unwrap();
+        });
+        ["0.mid", "2.mid"].iter().for_each(|name| {
+            std::fs::write(dir.0.join(name), b"MThd").unwrap();
+        });
+
+        let midi_player = dir.play(&[]);
+        let midi_recv = midi_player.get_midi_in_recv();
+        let notice_recv = midi_player.get_notice_recv();
+        let key = || loop {
+            let midi = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+            if midi.message[0] == 0x90 {
+                break midi.message[1];
+            }
+        };
+        let notices = || -> Vec<String> {
+            notice_recv
+                .try_iter()
+                .filter_map(|notice| match notice {
+                    Notice::Song { name, .. } => Some(name),
+                    Notice::Skipped { name, .. } => Some(format!("-{}", name)),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Broken files are skipped on the way, the first one as well.
+        assert_eq!(key(), 60);
+        assert_eq!(key(), 64);
+        assert_eq!(notices(), vec!["1.mid", "-0.mid", "3.mid", "-2.mid"]);
+
+        midi_player.transport.previous();
+        assert_eq!(key(), 60);
+        midi_player.transport.previous();
+        midi_player.transport.next();
+        assert_eq!(key(), 64);
+
+        // A new song is heard in full.
+        midi_player.transport.toggle_mute(Part::Channel(0));
+        midi_player.transport.previous();
+        assert_eq!(key(), 60);
+    }
+
+    #[test]
+    fn midi_player_meta() {
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(480.into())));
+        let meta = |delta: u32, message| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(message),
+        };
+        smf.tracks.push(vec![
+            meta(0, MetaMessage::TrackName(b"Piano")),
+            meta(0, MetaMessage::KeySignature(2, false)),
+            meta(48, MetaMessage::Tempo(400_000.into())),
+            meta(480, MetaMessage::EndOfTrack),
+        ]);
+        let path = TempPath::with_smf(&smf);
+        let midi_player = path.play(&[]);
+        let notice_recv = midi_player.get_notice_recv();
+        let file_name = path.0.file_name().unwrap().to_str().unwrap();
+        assert!(matches!(
+            notice_recv.recv_timeout(Duration::from_secs(1)),
+            Ok(Notice::Song { name, index: 0, count: 1, .. }) if name == file_name
+        ));
+        let recv = || match notice_recv.recv_timeout(Duration::from_secs(1)).unwrap() {
+            Notice::Meta { meta, timestamp } => (meta, timestamp),
+            notice => panic!("unexpected {:?}", notice),
+        };
+        let track_name = Meta::TrackName {
+            track: 0,
+            name: "Piano".to_owned(),
+        };
+        let key = Meta::KeySignature(2, false);
+        let tempo = Meta::Tempo(400_000);
+        assert_eq!(recv(), (track_name.clone(), time::Duration::ZERO));
+        assert_eq!(recv(), (key.clone(), time::Duration::ZERO));
+        assert_eq!(recv(), (tempo.clone(), time::Duration::milliseconds(50)));
+        assert_eq!(key.to_string(), "D major");
+        assert_eq!(tempo.to_string(), "150 BPM");
+
+        // What still holds is told again after seeking.
+        midi_player
+            .transport
+            .seek(time::Duration::milliseconds(100));
+        assert!(matches!(
+            notice_recv.recv_timeout(Duration::from_secs(1)),
+            Ok(Notice::Seeked { .. })
+        ));
+        let at = time::Duration::milliseconds(100);
+        assert_eq!(recv(), (tempo, at));
+        assert_eq!(recv(), (key, at));
+        assert_eq!(recv(), (track_name, at));
     }
 }
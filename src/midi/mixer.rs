@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{unbounded, Receiver};
+
+use crate::MidiData;
+
+/// Something to mute or solo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Part {
+    /// An SMF track, counting from 0
+    Track(usize),
+    /// A MIDI channel, counting from 0
+    Channel(u8),
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Part::Track(track) => write!(f, "track {}", track + 1),
+            Part::Channel(channel) => write!(f, "channel {}", channel + 1),
+        }
+    }
+}
+
+/// Which tracks and channels of a file, or channels of live input, are
+/// heard and seen.
+///
+/// A note plays unless its track or channel is muted, or some other track
+/// (or channel) is soloed. Clones share their state.
+#[derive(Clone, Default)]
+pub struct Mixer {
+    state: Arc<RwLock<MixerState>>,
+}
+
+#[derive(Default)]
+struct MixerState {
+    muted: BTreeSet<Part>,
+    soloed: BTreeSet<Part>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn toggle_mute(&self, part: Part) {
+        toggle(&mut self.state.write().unwrap().muted, part);
+    }
+
+    pub fn toggle_solo(&self, part: Part) {
+        toggle(&mut self.state.write().unwrap().soloed, part);
+    }
+
+    /// Unmute and unsolo everything.
+    pub fn clear(&self) {
+        *self.state.write().unwrap() = MixerState::default();
+    }
+
+    pub fn is_muted(&self, part: Part) -> bool {
+        self.state.read().unwrap().muted.contains(&part)
+    }
+
+    pub fn is_soloed(&self, part: Part) -> bool {
+        self.state.read().unwrap().soloed.contains(&part)
+    }
+
+    /// Whether notes on `channel` of `track` play.
+    pub fn is_audible(&self, track: usize, channel: u8) -> bool {
+        let state = self.state.read().unwrap();
+        state.is_heard(Part::Track(track)) && state.is_heard(Part::Channel(channel))
+    }
+
+    /// Whether live `message` is let through: anything but note ons on
+    /// channels not heard. Note offs always are, so no note is left hanging
+    /// when its channel is muted.
+    pub fn lets_through(&self, message: &[u8]) -> bool {
+        match message {
+            [status @ 0x90..=0x9F, _, velocity] if *velocity > 0 => self
+                .state
+                .read()
+                .unwrap()
+                .is_heard(Part::Channel(status & 0x0F)),
+            _ => true,
+        }
+    }
+
+    /// Pass on the live messages from `midi_recv` it lets through.
+    ///
+    /// Once `midi_recv` disconnects, the returned receiver disconnects too.
+    pub fn tap(&self, midi_recv: Receiver<MidiData>) -> (Receiver<MidiData>, JoinHandle<()>) {
+        let mixer = self.clone();
+        let (midi_send, tapped_recv) = unbounded();
+        let handler = thread::spawn(move || {
+            midi_recv
+                .iter()
+                .filter(|midi| mixer.lets_through(&midi.message))
+                .for_each(|midi| {
+                    let _send = midi_send.send(midi);
+                });
+        });
+        (tapped_recv, handler)
+    }
+}
+
+impl MixerState {
+    /// Whether `part` is neither muted nor left out by a solo of its kind.
+    fn is_heard(&self, part: Part) -> bool {
+        let same_kind = |other: &Part| {
+            matches!(
+                (part, other),
+                (Part::Track(_), Part::Track(_)) | (Part::Channel(_), Part::Channel(_))
+            )
+        };
+        !self.muted.contains(&part)
+            && (!self.soloed.iter().any(same_kind) || self.soloed.contains(&part))
+    }
+}
+
+fn toggle(parts: &mut BTreeSet<Part>, part: Part) {
+    if !parts.remove(&part) {
+        parts.insert(part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::unbounded;
+    use time::Duration;
+
+    use super::{Mixer, Part};
+    use crate::MidiData;
+
+    #[test]
+    fn mixer_mute_solo() {
+        let mixer = Mixer::new();
+        assert!(mixer.is_audible(0, 0));
+
+        mixer.toggle_mute(Part::Channel(9));
+        assert!(!mixer.is_audible(0, 9));
+        assert!(mixer.is_audible(0, 0));
+
+        // Soloing a track silences the others, but not its muted channels.
+        mixer.toggle_solo(Part::Track(1));
+        assert!(!mixer.is_audible(0, 0));
+        assert!(mixer.is_audible(1, 0));
+        assert!(!mixer.is_audible(1, 9));
+        mixer.toggle_solo(Part::Channel(0));
+        assert!(mixer.is_audible(1, 0));
+        assert!(!mixer.is_audible(1, 1));
+
+        mixer.toggle_mute(Part::Channel(9));
+        mixer.toggle_solo(Part::Channel(0));
+        assert!(!mixer.is_muted(Part::Channel(9)));
+        assert!(mixer.is_soloed(Part::Track(1)));
+        assert!(mixer.is_audible(1, 9));
+
+        mixer.clear();
+        assert!(mixer.is_audible(0, 0));
+    }
+
+    #[test]
+    fn mixer_live() {
+        let mixer = Mixer::new();
+        let (midi_send, midi_recv) = unbounded();
+        let (tapped_recv, handler) = mixer.tap(midi_recv);
+
+        mixer.toggle_mute(Part::Channel(9));
+        assert!(!mixer.lets_through(&[0x99, 36, 100]));
+        assert!(mixer.lets_through(&[0x90, 60, 100]));
+        // Releasing and the rest are let through.
+        assert!(mixer.lets_through(&[0x89, 36, 0]));
+        assert!(mixer.lets_through(&[0x99, 36, 0]));
+        assert!(mixer.lets_through(&[0xB9, 64, 0]));
+        assert!(mixer.lets_through(&[0xF8]));
+
+        mixer.toggle_solo(Part::Channel(1));
+        [
+            vec![0x90, 60, 100],
+            vec![0x91, 60, 100],
+            vec![0x99, 36, 100],
+        ]
+        .into_iter()
+        .for_each(|message| {
+            let _ = midi_send.send(MidiData {
+                message,
+                timestamp: Duration::ZERO,
+                source: 0,
+            });
+        });
+        drop(midi_send);
+        let passed: Vec<Vec<u8>> = tapped_recv.iter().map(|midi| midi.message).collect();
+        assert_eq!(passed, vec![vec![0x91, 60, 100]]);
+        handler.join().unwrap();
+    }
+}
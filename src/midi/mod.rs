@@ -1,20 +1,66 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::time::Instant;
+use std::sync::Arc;
 
 use crossbeam_channel::Receiver;
-use time::Duration;
 
-use crate::{options::Options, MidiData};
+use crate::{options::Options, MidiData, Notice};
+pub use clock::Clock;
+pub use error::MidiError;
+pub use log_player::LogPlayer;
 pub use midi_in::MidiIn;
-pub use midi_player::MidiPlayer;
+pub use midi_player::{list_sequences, MidiPlayer};
+pub use mixer::{Mixer, Part};
+pub use osc_in::OscIn;
+pub use playback::{Transport, SPEEDS};
+pub use ports::list_ports;
+pub use recorder::{Recorder, PPQS, TEMPOS};
+pub use rtp_midi_in::RtpMidiIn;
+pub use song::{Beat, Meta, Score, SongNote};
+pub use stream_in::StreamIn;
 
+mod clock;
+mod error;
+mod event_log;
+mod hotplug;
+mod input_backend;
+mod log_player;
 mod midi_in;
 mod midi_player;
+mod mixer;
+mod osc;
+mod osc_in;
+mod output;
+mod playback;
+mod playlist;
+mod ports;
+mod recorder;
+mod rtp_midi;
+mod rtp_midi_in;
+mod song;
+mod stream_in;
+mod stream_parser;
+#[cfg(test)]
+mod test_util;
+mod thru;
+mod timers;
 
 pub trait MidiProvider {
     fn get_midi_in_recv(&self) -> Receiver<MidiData>;
-    fn get_epoch(&self) -> Instant;
-    // fn get_ellapsed(&self) -> Duration;
-    fn new(opts: &Options) -> Self;
+    fn get_notice_recv(&self) -> Receiver<Notice>;
+    /// Time the renderers draw at, matching the timestamps of the messages
+    fn get_clock(&self) -> Clock;
+    /// Playback controls, for providers that can be controlled
+    fn get_transport(&self) -> Option<Transport>;
+    /// Records the input, for providers of live MIDI
+    fn get_recorder(&self) -> Option<Recorder>;
+    /// Mutes and solos tracks of a file or channels of live MIDI, for
+    /// providers that can
+    fn get_mixer(&self) -> Option<Mixer>;
+    /// Every note to be played, for providers that know ahead. When the
+    /// playlist moves on, the next score comes with [`Notice::Song`]
+    fn get_score(&self) -> Option<Arc<Score>>;
+    fn new(opts: &Options) -> Result<Self, MidiError>
+    where
+        Self: Sized;
 }
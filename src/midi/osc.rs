@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Decoding of Open Sound Control 1.0 packets.
+
+/// An argument of an OSC message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Argument {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Blob(Vec<u8>),
+    /// `T`, `F`, `N` and `I`, which have no data
+    Other(char),
+    /// `m`: port, then a three byte MIDI message
+    Midi([u8; 4]),
+}
+
+impl Argument {
+    /// The argument as a number, rounded; strings and blobs have none.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Argument::Int(int) => Some(*int),
+            Argument::Float(float) => Some(float.round() as i64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<Argument>,
+}
+
+/// The messages in `packet`: the message itself, or every message in the
+/// bundle and the bundles in it. Time tags are ignored, so bundled
+/// messages are meant for now. Malformed parts are left out.
+pub fn parse_packet(packet: &[u8]) -> Vec<OscMessage> {
+    let mut messages = Vec::new();
+    collect(packet, &mut messages);
+    messages
+}
+
+fn collect(packet: &[u8], messages: &mut Vec<OscMessage>) {
+    match packet.strip_prefix(b"#bundle\0") {
+        // Skip the time tag, then take the elements, each after its size.
+        Some(bundle) => {
+            let mut rest = bundle.get(8..).unwrap_or_default();
+            while let Some(size) = rest.get(..4) {
+                let size = i32::from_be_bytes(size.try_into().unwrap()).max(0) as usize;
+                match rest.get(4..4 + size) {
+                    Some(element) => collect(element, messages),
+                    None => return,
+                }
+                rest = &rest[4 + size..];
+            }
+        }
+        None => messages.extend(parse_message(packet)),
+    }
+}
+
+fn parse_message(packet: &[u8]) -> Option<OscMessage> {
+    let mut reader = Reader(packet);
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return None;
+    }
+    // Really old senders leave the type tags out.
+    let tags = match reader.0.is_empty() {
+        true => String::from(","),
+        false => reader.string()?,
+    };
+    let args = tags
+        .strip_prefix(',')?
+        .chars()
+        .map(|tag| match tag {
+            'i' => Some(Argument::Int(i32::from_be_bytes(reader.take()?) as i64)),
+            'h' => Some(Argument::Int(i64::from_be_bytes(reader.take()?))),
+            'f' => Some(Argument::Float(f32::from_be_bytes(reader.take()?) as f64)),
+            'd' => Some(Argument::Float(f64::from_be_bytes(reader.take()?))),
+            's' | 'S' => Some(Argument::String(reader.string()?)),
+            'b' => {
+                let size = i32::from_be_bytes(reader.take()?).max(0) as usize;
+                let blob = reader.padded(size)?.to_vec();
+                Some(Argument::Blob(blob))
+            }
+            'm' => Some(Argument::Midi(reader.take()?)),
+            // Other fixed-size types are read, but not worth telling apart.
+            'c' | 'r' => reader.take::<4>().map(|_| Argument::Other(tag)),
+            't' => reader.take::<8>().map(|_| Argument::Other(tag)),
+            'T' | 'F' | 'N' | 'I' | '[' | ']' => Some(Argument::Other(tag)),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(OscMessage { address, args })
+}
+
+/// Reads OSC data, which comes in multiples of four bytes.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.0.get(..N)?.try_into().ok()?;
+        self.0 = &self.0[N..];
+        Some(bytes)
+    }
+
+    /// `size` bytes, skipping the zeros padding them to four.
+    fn padded(&mut self, size: usize) -> Option<&'a [u8]> {
+        let bytes = self.0.get(..size)?;
+        self.0 = self.0.get(size.next_multiple_of(4)..)?;
+        Some(bytes)
+    }
+
+    /// A string, ended by a zero.
+    fn string(&mut self) -> Option<String> {
+        let end = self.0.iter().position(|byte| *byte == 0)?;
+        let bytes = self.0;
+        self.padded(end + 1)?;
+        Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_packet, Argument, OscMessage};
+
+    #[test]
+    fn osc_parse() {
+        // As SuperCollider sends ["/noteon", 0, 60, 0.5].
+        let message = b"/noteon\0,iif\0\0\0\0\0\0\0\0\0\0\0\x3C\x3F\0\0\0";
+        assert_eq!(
+            parse_packet(message),
+            vec![OscMessage {
+                address: "/noteon".to_owned(),
+                args: vec![Argument::Int(0), Argument::Int(60), Argument::Float(0.5)],
+            }]
+        );
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        let inner = b"/a\0\0,sbT\0\0\0\0hi\0\0\0\0\0\x03\x01\x02\x03\0";
+        bundle.extend_from_slice(&(inner.len() as i32).to_be_bytes());
+        bundle.extend_from_slice(inner);
+        let mut outer = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        outer.extend_from_slice(&(bundle.len() as i32).to_be_bytes());
+        outer.extend_from_slice(&bundle);
+        // Cut short: left out.
+        outer.extend_from_slice(&[0, 0, 0, 12, b'/']);
+        assert_eq!(
+            parse_packet(&outer),
+            vec![OscMessage {
+                address: "/a".to_owned(),
+                args: vec![
+                    Argument::String("hi".to_owned()),
+                    Argument::Blob(vec![1, 2, 3]),
+                    Argument::Other('T'),
+                ],
+            }]
+        );
+
+        assert!(parse_packet(b"/a\0\0,i\0\0\0\0").is_empty());
+        assert!(parse_packet(b"noteon\0\0,\0\0\0").is_empty());
+        assert_eq!(parse_packet(b"/a\0\0")[0].args, Vec::new());
+    }
+}
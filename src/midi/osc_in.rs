@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
+
+use super::clock::Clock;
+use super::error::{MidiError, Result};
+use super::midi_in::tap;
+use super::mixer::Mixer;
+use super::osc::{parse_packet, Argument, OscMessage};
+use super::playback::Transport;
+use super::recorder::Recorder;
+use super::rtp_midi_in::bind;
+use super::song::Score;
+use super::thru::Thru;
+use crate::{midi::MidiProvider, options::Options, Message, MidiData, Notice};
+
+/// How often the socket stops waiting to see whether to close.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which OSC addresses stand for which MIDI messages.
+#[derive(Debug, Clone)]
+pub struct AddressMap {
+    /// Takes channel, note and velocity; without a velocity, 64. Velocities
+    /// may be floats from 0 to 1, as amplitudes are in SuperCollider.
+    pub note_on: String,
+    /// Takes channel, note and velocity; without a velocity, 0
+    pub note_off: String,
+}
+
+impl AddressMap {
+    /// The MIDI message `message` stands for, if any. Channels count from
+    /// 0; out of range numbers are clamped.
+    fn to_midi(&self, message: &OscMessage) -> Option<Message> {
+        let (status, velocity) = match message.address.as_str() {
+            address if address == self.note_on => (0x90, 64),
+            address if address == self.note_off => (0x80, 0),
+            _ => return None,
+        };
+        let arg = |index: usize| message.args.get(index).and_then(|arg| arg.as_int());
+        let channel = arg(0)?.clamp(0, 15) as u8;
+        let note = arg(1)?.clamp(0, 127) as u8;
+        let velocity = match message.args.get(2) {
+            Some(Argument::Float(amplitude)) if (0.0..=1.0).contains(amplitude) => {
+                (amplitude * 127.0).round() as i64
+            }
+            _ => arg(2).unwrap_or(velocity),
+        };
+        let velocity = velocity.clamp(0, 127) as u8;
+        Some(vec![status | channel, note, velocity])
+    }
+}
+
+/// MIDI from Open Sound Control messages over UDP, as live coding tools
+/// such as SuperCollider and TidalCycles send.
+///
+/// Messages to the addresses of an [`AddressMap`] become MIDI messages;
+/// others are ignored. Each address sending is a source, numbered in the
+/// order they first sent something.
+pub struct OscIn {
+    midi_recv: Receiver<MidiData>,
+    notice_recv: Receiver<Notice>,
+    clock: Clock,
+    /// Dropped to stop listening
+    stop_send: Option<Sender<()>>,
+    handler: Option<JoinHandle<()>>,
+    recorder: Recorder,
+    /// Mutes and solos channels
+    mixer: Mixer,
+    /// Pass the messages on to `midi_recv`; see [`tap`]
+    taps: Vec<JoinHandle<()>>,
+}
+
+impl MidiProvider for OscIn {
+    fn get_midi_in_recv(&self) -> Receiver<MidiData> {
+        self.midi_recv.clone()
+    }
+
+    fn get_notice_recv(&self) -> Receiver<Notice> {
+        self.notice_recv.clone()
+    }
+
+    fn get_clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    fn get_transport(&self) -> Option<Transport> {
+        None
+    }
+
+    fn get_recorder(&self) -> Option<Recorder> {
+        Some(self.recorder.clone())
+    }
+
+    fn get_mixer(&self) -> Option<Mixer> {
+        Some(self.mixer.clone())
+    }
+
+    fn get_score(&self) -> Option<Arc<Score>> {
+        None
+    }
+
+    fn new(opts: &Options) -> Result<Self> {
+        let socket = bind(opts.osc.unwrap_or_default())?;
+        Self::with_socket(opts, socket)
+    }
+}
+
+impl OscIn {
+    fn with_socket(opts: &Options, socket: UdpSocket) -> Result<Self> {
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let clock = Clock::new();
+        let mixer = Mixer::new();
+        let (recorder, midi_recv, taps) = tap(opts, &clock, &notice_send, &mixer, midi_recv)?;
+        let thru = Thru::from_options(opts)?.map(|thru| thru.with_mixer(mixer.clone()));
+        let map = AddressMap {
+            note_on: opts.osc_note_on.clone(),
+            note_off: opts.osc_note_off.clone(),
+        };
+        socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|e| MidiError::Backend(e.to_string()))?;
+
+        let (stop_send, stop_recv) = bounded::<()>(0);
+        let listen_clock = clock.clone();
+        let handler = thread::spawn(move || {
+            let mut senders = Vec::<SocketAddr>::new();
+            let mut buffer = [0; 65536];
+            while let Err(TryRecvError::Empty) = stop_recv.try_recv() {
+                // Timeouts and errors alike just go round again.
+                let (read, from) = match socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+                let timestamp = listen_clock.now();
+                let source = match senders.iter().position(|sender| *sender == from) {
+                    Some(source) => source,
+                    None => {
+                        senders.push(from);
+                        let _send = notice_send.send(Notice::Connected {
+                            source: senders.len() - 1,
+                            port: format!("OSC from {}", from),
+                        });
+                        senders.len() - 1
+                    }
+                };
+                for message in parse_packet(&buffer[..read])
+                    .iter()
+                    .filter_map(|message| map.to_midi(message))
+                {
+                    if let Some(thru) = &thru {
+                        thru.send(&message);
+                    }
+                    let _send = midi_send.send(MidiData {
+                        message,
+                        timestamp,
+                        source,
+                    });
+                }
+            }
+        });
+
+        Ok(OscIn {
+            midi_recv,
+            notice_recv,
+            clock,
+            stop_send: Some(stop_send),
+            handler: Some(handler),
+            recorder,
+            mixer,
+            taps,
+        })
+    }
+
+    /// Stop listening, and write what is being recorded or logged.
+    pub fn close(&mut self) {
+        self.stop_send.take();
+        self.handler
+            .take()
+            .into_iter()
+            .chain(self.taps.drain(..))
+            .for_each(|handler| {
+                let _ = handler.join();
+            });
+    }
+}
+
+impl Drop for OscIn {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use crossbeam_channel::RecvTimeoutError;
+
+    use super::OscIn;
+    use crate::midi::MidiProvider;
+    use crate::{Notice, Options};
+
+    #[test]
+    fn osc_in_notes() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let opts = Options::parse_from(["mirmidivi-rs", "--osc-note-off", "/off"]);
+        let mut midi = OscIn::with_socket(&opts, socket).unwrap();
+        let midi_recv = midi.get_midi_in_recv();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        [
+            // Channel 9, note 36, velocity 100.5
+            &b"/noteon\0,iif\0\0\0\0\0\0\0\x09\0\0\0\x24\x42\xC9\0\0"[..],
+            // Channel 0, note 60, amplitude 0.25
+            b"/noteon\0,iif\0\0\0\0\0\0\0\0\0\0\0\x3C\x3E\x80\0\0",
+            // Not mapped
+            b"/noteoff\0\0\0\0,ii\0\0\0\0\x09\0\0\0\x24",
+            // Without a velocity
+            b"/off\0\0\0\0,ii\0\0\0\0\x09\0\0\0\x24",
+        ]
+        .iter()
+        .for_each(|packet| {
+            sender.send_to(packet, address).unwrap();
+        });
+
+        let on = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(on.message, vec![0x99, 36, 101]);
+        assert_eq!(on.source, 0);
+        let amplitude = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(amplitude.message, vec![0x90, 60, 32]);
+        let off = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(off.message, vec![0x89, 36, 0]);
+        assert!(matches!(
+            midi.get_notice_recv().try_recv(),
+            Ok(Notice::Connected { source: 0, port }) if port.starts_with("OSC from 127.0.0.1:")
+        ));
+
+        midi.close();
+        assert!(matches!(
+            midi_recv.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeSet;
+
+use nodi::{
+    midly::{num::u4, MidiMessage},
+    Connection, MidiEvent,
+};
+
+/// Control change silencing a whole channel.
+const ALL_NOTES_OFF: u8 = 123;
+
+/// A connection to a synthesizer that remembers the sounding notes, so
+/// playback can be paused or stopped without leaving notes hanging.
+pub struct Output {
+    connection: Option<Box<dyn Connection + Send>>,
+    /// Sounding notes as `(channel, key)`
+    sounding: BTreeSet<(u8, u8)>,
+    /// While held, no new notes are started
+    held: bool,
+}
+
+impl Output {
+    pub fn new(connection: Box<dyn Connection + Send>) -> Self {
+        Self {
+            connection: Some(connection),
+            sounding: BTreeSet::new(),
+            held: false,
+        }
+    }
+
+    /// Stop starting notes and silence the sounding ones, or carry on.
+    pub fn hold(&mut self, held: bool) {
+        self.held = held;
+        if held {
+            self.all_notes_off();
+        }
+    }
+
+    /// Silence everything and let go of the connection.
+    pub fn close(&mut self) {
+        self.all_notes_off();
+        self.connection.take();
+    }
+}
+
+impl Connection for Output {
+    fn play(&mut self, event: MidiEvent) -> bool {
+        let channel = u8::from(event.channel);
+        match event.message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                if self.held {
+                    return true;
+                }
+                self.sounding.insert((channel, u8::from(key)));
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                self.sounding.remove(&(channel, u8::from(key)));
+            }
+            _ => (),
+        }
+        match &mut self.connection {
+            Some(connection) => connection.play(event),
+            None => true,
+        }
+    }
+
+    /// Release the sounding notes one by one, for synthesizers ignoring
+    /// All Notes Off, then send All Notes Off on every channel anyway.
+    fn all_notes_off(&mut self) {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+        self.sounding.iter().for_each(|&(channel, key)| {
+            connection.play(MidiEvent {
+                channel: channel.into(),
+                message: MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                },
+            });
+        });
+        self.sounding.clear();
+        (0..16).for_each(|channel| {
+            connection.play(MidiEvent {
+                channel: u4::from(channel),
+                message: MidiMessage::Controller {
+                    controller: ALL_NOTES_OFF.into(),
+                    value: 0.into(),
+                },
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use nodi::{midly::MidiMessage, Connection, MidiEvent};
+
+    use super::Output;
+
+    struct MockConnection(Arc<Mutex<Vec<MidiEvent>>>);
+
+    impl Connection for MockConnection {
+        fn play(&mut self, event: MidiEvent) -> bool {
+            self.0.lock().unwrap().push(event);
+            true
+        }
+    }
+
+    fn note_on(channel: u8, key: u8) -> MidiEvent {
+        MidiEvent {
+            channel: channel.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+        }
+    }
+
+    fn note_off(channel: u8, key: u8) -> MidiEvent {
+        MidiEvent {
+            channel: channel.into(),
+            message: MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn hold_releases_sounding_notes() {
+        let played = Arc::new(Mutex::new(Vec::new()));
+        let mut output = Output::new(Box::new(MockConnection(played.clone())));
+
+        output.play(note_on(0, 60));
+        output.play(note_on(9, 36));
+        output.play(note_off(0, 60));
+        played.lock().unwrap().clear();
+
+        output.hold(true);
+        output.play(note_on(0, 64));
+        {
+            let played = played.lock().unwrap();
+            assert_eq!(played[0], note_off(9, 36));
+            // All Notes Off on every channel, and nothing started while held.
+            assert_eq!(played.len(), 1 + 16);
+        }
+
+        output.hold(false);
+        output.play(note_on(0, 64));
+        played.lock().unwrap().clear();
+        output.close();
+        assert_eq!(played.lock().unwrap()[0], note_off(0, 64));
+        assert!(output.play(note_on(0, 60)));
+        assert_eq!(played.lock().unwrap().len(), 1 + 16);
+    }
+}
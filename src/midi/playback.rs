@@ -0,0 +1,580 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::{Range, RangeInclusive},
+    sync::{Arc, RwLock},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use nodi::{midly::MidiMessage, Connection, MidiEvent};
+use time::Duration;
+
+use super::{
+    clock::Clock,
+    error::MidiError,
+    mixer::{Mixer, Part},
+    output::Output,
+    playlist::Playlist,
+    song::{Meta, Song, SongEventKind},
+};
+use crate::{MidiData, Notice};
+
+/// Slowest and fastest playback, as a factor of the file's tempo.
+pub const SPEEDS: RangeInclusive<f64> = 0.5..=2.0;
+
+enum Command {
+    Play,
+    Pause,
+    TogglePause,
+    Stop,
+    Seek(Duration),
+    Loop(Option<Range<Duration>>),
+    Speed(f64),
+    /// Something was muted or soloed
+    Mix,
+    /// Play the next file of the playlist, or the previous one
+    Skip {
+        forward: bool,
+    },
+    Close,
+}
+
+/// Why [`Playback`] woke up.
+enum Wake {
+    Command(Command),
+    /// The next event is due
+    Event,
+    /// The end of the loop is reached
+    Wrap,
+    /// The end of the song is reached, and the playlist goes on
+    End,
+}
+
+/// Remote control for a playing file.
+///
+/// Cheap to clone, so renderers can keep one for their key bindings.
+#[derive(Clone)]
+pub struct Transport {
+    command_send: Sender<Command>,
+    /// The song playing, replaced when the playlist moves on
+    song: Arc<RwLock<Arc<Song>>>,
+    /// The region played over and over, if any
+    looped: Arc<RwLock<Option<Range<Duration>>>>,
+    mixer: Mixer,
+}
+
+impl Transport {
+    pub fn play(&self) {
+        self.send(Command::Play);
+    }
+
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    pub fn toggle_pause(&self) {
+        self.send(Command::TogglePause);
+    }
+
+    /// Pause and go back to the beginning.
+    pub fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    /// Go to `position`, clamped to the song. Playing or paused stays so.
+    pub fn seek(&self, position: Duration) {
+        self.send(Command::Seek(position));
+    }
+
+    /// Go to the start of `bar`, counting from 1.
+    pub fn seek_to_bar(&self, bar: usize) {
+        let song = self.song();
+        let bars = &song.bars;
+        let index = bar.saturating_sub(1).min(bars.len().saturating_sub(1));
+        self.seek(bars.get(index).copied().unwrap_or(Duration::ZERO));
+    }
+
+    /// Play from the beginning.
+    pub fn restart(&self) {
+        self.seek(Duration::ZERO);
+        self.play();
+    }
+
+    /// Play `speed` times as fast as the file says, within [`SPEEDS`].
+    ///
+    /// Timestamps stay in song time, so renderers follow along.
+    pub fn set_speed(&self, speed: f64) {
+        self.send(Command::Speed(speed.clamp(*SPEEDS.start(), *SPEEDS.end())));
+    }
+
+    /// Play `range` over and over, going back to its start whenever its end
+    /// is reached. An empty range stops looping.
+    pub fn set_loop(&self, range: Range<Duration>) {
+        let range = range.start.max(Duration::ZERO)..range.end.min(self.song().length());
+        let range = Some(range).filter(|range| !range.is_empty());
+        *self.looped.write().unwrap() = range.clone();
+        self.send(Command::Loop(range));
+    }
+
+    /// Loop from `position` on, up to the end of the current loop if it is
+    /// still ahead, or else of the song.
+    pub fn set_loop_start(&self, position: Duration) {
+        let end = self
+            .loop_range()
+            .map(|range| range.end)
+            .filter(|&end| position < end);
+        self.set_loop(position..end.unwrap_or(self.song().length()));
+    }
+
+    /// Loop up to `position`, from the start of the current loop if it is
+    /// before, or else of the song.
+    pub fn set_loop_end(&self, position: Duration) {
+        let start = self
+            .loop_range()
+            .map(|range| range.start)
+            .filter(|&start| start < position);
+        self.set_loop(start.unwrap_or(Duration::ZERO)..position);
+    }
+
+    pub fn clear_loop(&self) {
+        self.set_loop(Duration::ZERO..Duration::ZERO);
+    }
+
+    pub fn loop_range(&self) -> Option<Range<Duration>> {
+        self.looped.read().unwrap().clone()
+    }
+
+    /// Play the next file of the playlist from the start.
+    pub fn next(&self) {
+        self.send(Command::Skip { forward: true });
+    }
+
+    /// Play the previous file of the playlist from the start.
+    pub fn previous(&self) {
+        self.send(Command::Skip { forward: false });
+    }
+
+    /// Silence `part`, or hear it again. Muted notes are not played, drawn
+    /// or sent to the output.
+    pub fn toggle_mute(&self, part: Part) {
+        self.mixer.toggle_mute(part);
+        self.send(Command::Mix);
+    }
+
+    /// Hear `part` alone, along with any other part soloed.
+    pub fn toggle_solo(&self, part: Part) {
+        self.mixer.toggle_solo(part);
+        self.send(Command::Mix);
+    }
+
+    /// Hear every part again.
+    pub fn clear_mix(&self) {
+        self.mixer.clear();
+        self.send(Command::Mix);
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// Tracks and channels with notes to mute or solo.
+    pub fn parts(&self) -> Vec<Part> {
+        let mut parts = BTreeSet::new();
+        self.song().events.iter().for_each(|event| {
+            if let SongEventKind::Midi(midi) = &event.kind {
+                if let MidiMessage::NoteOn { .. } = midi.message {
+                    parts.insert(Part::Track(event.track));
+                    parts.insert(Part::Channel(u8::from(midi.channel)));
+                }
+            }
+        });
+        parts.into_iter().collect()
+    }
+
+    pub(super) fn song(&self) -> Arc<Song> {
+        self.song.read().unwrap().clone()
+    }
+
+    /// Stop playing for good; see [`Playback::run`].
+    pub(super) fn close(&self) {
+        self.send(Command::Close);
+    }
+
+    /// Bar playing at `position`, counting from 1.
+    pub fn bar_at(&self, position: Duration) -> usize {
+        self.song()
+            .bars
+            .partition_point(|&bar| bar <= position)
+            .max(1)
+    }
+
+    fn send(&self, command: Command) {
+        // The player is gone when closed; nothing left to control.
+        let _send = self.command_send.send(command);
+    }
+}
+
+/// Plays a [`Song`] by the [`Clock`], taking orders from [`Transport`]s,
+/// then the rest of the [`Playlist`].
+pub struct Playback {
+    song: Arc<Song>,
+    playlist: Playlist,
+    /// What the transports see of the song and loop
+    shared_song: Arc<RwLock<Arc<Song>>>,
+    shared_loop: Arc<RwLock<Option<Range<Duration>>>>,
+    /// Song time, at the playback speed
+    clock: Clock,
+    midi_send: Sender<MidiData>,
+    notice_send: Sender<Notice>,
+    output: Option<Output>,
+    command_recv: Receiver<Command>,
+    /// Index of the next event to play
+    next: usize,
+    paused: bool,
+    looped: Option<Range<Duration>>,
+    mixer: Mixer,
+    /// Notes the renderers were told are sounding, as `(channel, key)`, and
+    /// the track playing them
+    sounding: BTreeMap<(u8, u8), usize>,
+}
+
+impl Playback {
+    /// Play `song`, the current file of `playlist`.
+    pub fn new(
+        song: Song,
+        playlist: Playlist,
+        clock: Clock,
+        midi_send: Sender<MidiData>,
+        notice_send: Sender<Notice>,
+        output: Option<Output>,
+    ) -> (Self, Transport) {
+        let (command_send, command_recv) = crossbeam_channel::unbounded();
+        let song = Arc::new(song);
+        let mixer = Mixer::new();
+        let transport = Transport {
+            command_send,
+            song: Arc::new(RwLock::new(song.clone())),
+            looped: Arc::new(RwLock::new(None)),
+            mixer: mixer.clone(),
+        };
+        let playback = Self {
+            song,
+            playlist,
+            shared_song: transport.song.clone(),
+            shared_loop: transport.looped.clone(),
+            clock,
+            midi_send,
+            notice_send,
+            output,
+            command_recv,
+            next: 0,
+            paused: false,
+            looped: None,
+            mixer,
+            sounding: BTreeMap::new(),
+        };
+        (playback, transport)
+    }
+
+    /// Play until closed, then release every note on the output. `skipped`
+    /// are the files passed over to get to the song, as [`Playlist::load_from`]
+    /// returns them.
+    pub fn run(mut self, skipped: Vec<(String, MidiError)>) {
+        self.clock.set(Duration::ZERO, true);
+        self.send_song();
+        self.tell_skipped(skipped);
+        loop {
+            match self.wait() {
+                Wake::Event => {
+                    let event = self.song.events[self.next].clone();
+                    self.next += 1;
+                    self.play(event.track, event.kind, event.time);
+                }
+                Wake::Wrap => {
+                    let start = self
+                        .looped
+                        .as_ref()
+                        .map_or(Duration::ZERO, |range| range.start);
+                    self.seek(start);
+                }
+                Wake::End => self.skip(true),
+                Wake::Command(Command::Close) => break,
+                Wake::Command(command) => self.apply(command),
+            }
+        }
+        if let Some(output) = &mut self.output {
+            output.close();
+        }
+    }
+
+    /// Wait for the next event or the end of the loop, whichever is due
+    /// first, unless a command comes sooner.
+    fn wait(&mut self) -> Wake {
+        let now = self.clock.now();
+        let event = self.song.events.get(self.next).map(|event| event.time);
+        // Once seeked past its end, the loop is left alone.
+        let wrap = self
+            .looped
+            .as_ref()
+            .map(|range| range.end)
+            .filter(|&end| now < end);
+        let (due, wake) = match (event, wrap) {
+            // Nothing to play until told otherwise.
+            _ if self.paused => return self.recv(),
+            (Some(event), Some(end)) if end <= event => (end, Wake::Wrap),
+            (Some(event), _) => (event, Wake::Event),
+            (None, Some(end)) => (end, Wake::Wrap),
+            (None, None) if self.playlist.step(self.playlist.current(), true).is_some() => {
+                (self.song.length(), Wake::End)
+            }
+            (None, None) => return self.recv(),
+        };
+        if due <= now {
+            return wake;
+        }
+        let timeout = ((due - now) / self.clock.speed()).try_into().unwrap();
+        match self.command_recv.recv_timeout(timeout) {
+            Ok(command) => Wake::Command(command),
+            Err(RecvTimeoutError::Timeout) => wake,
+            Err(RecvTimeoutError::Disconnected) => Wake::Command(Command::Close),
+        }
+    }
+
+    fn recv(&self) -> Wake {
+        Wake::Command(self.command_recv.recv().unwrap_or(Command::Close))
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Play if self.next >= self.song.events.len() => {
+                self.seek(Duration::ZERO);
+                self.set_paused(false);
+            }
+            Command::Play => self.set_paused(false),
+            Command::Pause => self.set_paused(true),
+            Command::TogglePause => self.set_paused(!self.paused),
+            Command::Stop => {
+                self.set_paused(true);
+                self.seek(Duration::ZERO);
+            }
+            Command::Seek(position) => self.seek(position),
+            Command::Loop(range) => {
+                self.looped = range;
+                // Set behind the playhead, the loop starts over at once.
+                let now = self.clock.now();
+                if let Some(range) = self.looped.clone().filter(|range| range.end <= now) {
+                    self.seek(range.start);
+                }
+            }
+            Command::Speed(speed) => self.clock.set_speed(speed),
+            Command::Mix => self.silence_muted(),
+            Command::Skip { forward } => self.skip(forward),
+            Command::Close => (),
+        }
+    }
+
+    /// Play the next file of the playlist that can be played, or the
+    /// previous one, from the start.
+    fn skip(&mut self, forward: bool) {
+        let (song, skipped) = match self.playlist.step(self.playlist.current(), forward) {
+            Some(index) => self.playlist.load_from(index, forward),
+            None => (None, Vec::new()),
+        };
+        if let Some(song) = song {
+            self.set_song(song);
+        }
+        self.tell_skipped(skipped);
+    }
+
+    /// Tell of the files passed over on the way to a song. Told after the
+    /// song, so they are not taken for its news.
+    fn tell_skipped(&self, skipped: Vec<(String, MidiError)>) {
+        skipped.into_iter().for_each(|(name, e)| {
+            let _send = self.notice_send.send(Notice::Skipped {
+                name,
+                reason: e.to_string(),
+            });
+        });
+    }
+
+    /// Play `song` from the start, without the loop or mix of the last one.
+    fn set_song(&mut self, song: Song) {
+        self.song = Arc::new(song);
+        *self.shared_song.write().unwrap() = self.song.clone();
+        self.looped = None;
+        *self.shared_loop.write().unwrap() = None;
+        // Tracks and channels of the last song need not match this one's.
+        self.mixer.clear();
+        self.send_song();
+        self.seek(Duration::ZERO);
+    }
+
+    fn send_song(&self) {
+        let _send = self.notice_send.send(Notice::Song {
+            name: self.playlist.name(),
+            index: self.playlist.current(),
+            count: self.playlist.len(),
+            score: self.song.score(),
+        });
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.clock.set(self.clock.now(), !paused);
+        if let Some(output) = &mut self.output {
+            output.hold(paused);
+        }
+    }
+
+    /// End the sounding notes that are muted now.
+    fn silence_muted(&mut self) {
+        let now = self.clock.now();
+        let muted: Vec<(u8, u8)> = self
+            .sounding
+            .iter()
+            .filter(|&(&(channel, _), &track)| !self.mixer.is_audible(track, channel))
+            .map(|(&note, _)| note)
+            .collect();
+        muted.into_iter().for_each(|(channel, key)| {
+            let note_off = MidiEvent {
+                channel: channel.into(),
+                message: MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                },
+            };
+            self.play(
+                self.sounding[&(channel, key)],
+                SongEventKind::Midi(note_off),
+                now,
+            );
+        });
+    }
+
+    /// Play an event of `track`, unless it is a note muted there.
+    fn play(&mut self, track: usize, event: SongEventKind, timestamp: Duration) {
+        let event = match event {
+            SongEventKind::Midi(event) => event,
+            SongEventKind::Meta(meta) => {
+                let _send = self.notice_send.send(Notice::Meta { meta, timestamp });
+                return;
+            }
+        };
+        let channel = u8::from(event.channel);
+        let audible = match event.message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                let audible = self.mixer.is_audible(track, channel);
+                if audible {
+                    self.sounding.insert((channel, u8::from(key)), track);
+                }
+                audible
+            }
+            // Muted notes never started.
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                self.sounding.remove(&(channel, u8::from(key))).is_some()
+            }
+            _ => true,
+        };
+        if !audible {
+            return;
+        }
+        if let Some(output) = &mut self.output {
+            output.play(event);
+        }
+        let mut message = Vec::with_capacity(8);
+        event.write(&mut message).unwrap();
+        self.send(message, timestamp);
+    }
+
+    fn send(&self, message: Vec<u8>, timestamp: Duration) {
+        let _send = self.midi_send.send(MidiData {
+            message,
+            timestamp,
+            source: 0,
+        });
+    }
+
+    /// Continue from `position` as if the song had been playing all along.
+    fn seek(&mut self, position: Duration) {
+        let position = position.clamp(Duration::ZERO, self.song.length());
+        let now = self.clock.now();
+
+        // End what is sounding where we leave; renderers told of the seek
+        // drop what was drawn after where we land.
+        if let Some(output) = &mut self.output {
+            output.all_notes_off();
+        }
+        std::mem::take(&mut self.sounding)
+            .into_keys()
+            .for_each(|(channel, key)| {
+                self.send(vec![0x80 | channel, key, 0], now);
+            });
+        let _send = self.notice_send.send(Notice::Seeked { position });
+
+        self.next = self.song.index_at(position);
+        self.chase()
+            .into_iter()
+            .for_each(|(track, event)| self.play(track, event, position));
+        self.clock.set(position, !self.paused);
+    }
+
+    /// Events setting things up as they are just before the next event: the
+    /// latest tempo, signatures and marker, the track names, then programs,
+    /// controllers, pitch bends and held notes.
+    fn chase(&self) -> Vec<(usize, SongEventKind)> {
+        let mut metas = BTreeMap::new();
+        let mut state = BTreeMap::new();
+        self.song.events[..self.next]
+            .iter()
+            .filter_map(|event| match &event.kind {
+                SongEventKind::Midi(midi) => Some((event.track, *midi)),
+                SongEventKind::Meta(meta) => {
+                    let key = match meta {
+                        Meta::Tempo(_) => (0, 0),
+                        Meta::TimeSignature(..) => (1, 0),
+                        Meta::KeySignature(..) => (2, 0),
+                        Meta::Marker(_) => (3, 0),
+                        Meta::TrackName { track, .. } => (4, *track),
+                        // Past words are not sung again.
+                        Meta::Lyric(_) | Meta::Text(_) => return None,
+                    };
+                    metas.insert(key, (event.track, meta.clone()));
+                    None
+                }
+            })
+            .for_each(|(track, event)| {
+                let channel = u8::from(event.channel);
+                match event.message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        state.insert((channel, 2, u8::from(key)), (track, event));
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        state.remove(&(channel, 2, u8::from(key)));
+                    }
+                    MidiMessage::ProgramChange { .. } => {
+                        state.insert((channel, 0, 0), (track, event));
+                    }
+                    MidiMessage::Controller { controller, .. } => {
+                        state.insert((channel, 1, u8::from(controller)), (track, event));
+                    }
+                    MidiMessage::PitchBend { .. } => {
+                        state.insert((channel, 1, 128), (track, event));
+                    }
+                    _ => (),
+                }
+            });
+        // Programs first, then controllers, then notes.
+        let mut events: Vec<_> = state.into_iter().collect();
+        events.sort_by_key(|&((channel, kind, number), _)| (kind, channel, number));
+        metas
+            .into_values()
+            .map(|(track, meta)| (track, SongEventKind::Meta(meta)))
+            .chain(
+                events
+                    .into_iter()
+                    .map(|(_, (track, event))| (track, SongEventKind::Midi(event))),
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::error::{MidiError, Result};
+use super::midi_player::{parse_file, read_file};
+use super::song::Song;
+use crate::options::Options;
+
+/// Extensions of the files picked from a directory.
+const EXTENSIONS: [&str; 3] = ["mid", "midi", "kar"];
+
+/// The MIDI files to play one after the other.
+pub struct Playlist {
+    files: Vec<PathBuf>,
+    /// Index of the file playing
+    current: usize,
+    /// Go back to the first file after the last one
+    repeat: bool,
+    /// Sequences to play of each file, all if empty
+    sequences: Vec<usize>,
+}
+
+impl Playlist {
+    /// The files and directories given by `--midifile`, each directory
+    /// standing for the MIDI files in it, in name order.
+    pub fn from_options(opts: &Options) -> Result<Self> {
+        let mut files = Vec::new();
+        for path in &opts.midifile {
+            let path = Path::new(path);
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    let mut found: Vec<PathBuf> = entries
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .filter(|path| path.is_file() && is_midi_file(path))
+                        .collect();
+                    if found.is_empty() {
+                        return Err(MidiError::NoMidiFiles {
+                            path: path.display().to_string(),
+                        });
+                    }
+                    found.sort();
+                    files.extend(found);
+                }
+                // Not a directory; whether it is a MIDI file shows when loaded.
+                Err(_) => files.push(path.to_owned()),
+            }
+        }
+        if opts.shuffle {
+            shuffle(&mut files, seed());
+        }
+        Ok(Self {
+            files,
+            current: 0,
+            repeat: opts.repeat,
+            sequences: opts.sequence.clone(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Index of the file playing, counting from 0.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn set_current(&mut self, index: usize) {
+        self.current = index.min(self.files.len().saturating_sub(1));
+    }
+
+    /// File name of the file playing, without its directory.
+    pub fn name(&self) -> String {
+        let path = &self.files[self.current];
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Read the file playing.
+    pub fn load(&self) -> Result<Song> {
+        let path = self.files[self.current].to_string_lossy();
+        let file = read_file(&path)?;
+        let smf = parse_file(&path, &file)?;
+        match self.sequences.is_empty() {
+            true => Ok(Song::new(&smf)),
+            false => Song::with_sequences(&smf, &self.sequences),
+        }
+    }
+
+    /// Make the first file that loads the one playing, trying `index`, then
+    /// on as by [`Self::step`]. Returns its song, if any file loads, and the
+    /// files passed over with why; if none loads, the one playing stays.
+    pub fn load_from(
+        &mut self,
+        index: usize,
+        forward: bool,
+    ) -> (Option<Song>, Vec<(String, MidiError)>) {
+        let current = self.current;
+        let mut index = Some(index);
+        let mut skipped = Vec::new();
+        while let Some(next) = index.filter(|_| skipped.len() < self.files.len()) {
+            self.set_current(next);
+            match self.load() {
+                Ok(song) => return (Some(song), skipped),
+                Err(e) => skipped.push((self.name(), e)),
+            }
+            index = self.step(next, forward);
+        }
+        self.set_current(current);
+        (None, skipped)
+    }
+
+    /// Index of the file after `index`, or before it. Past either end,
+    /// repeating playlists wrap around and others stop.
+    pub fn step(&self, index: usize, forward: bool) -> Option<usize> {
+        let len = self.files.len();
+        match forward {
+            true if index + 1 < len => Some(index + 1),
+            false if index > 0 => Some(index - 1),
+            true if self.repeat => Some(0),
+            false if self.repeat => Some(len - 1),
+            _ => None,
+        }
+    }
+}
+
+fn is_midi_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// Something different every run, to shuffle with.
+fn seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_nanos() as u64)
+}
+
+/// Fisher-Yates shuffle, drawing from xorshift64.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed.max(1);
+    (1..items.len()).rev().for_each(|i| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{shuffle, Playlist};
+    use crate::midi::test_util::TempPath;
+    use crate::midi::MidiError;
+
+    #[test]
+    fn playlist_directory() {
+        let dir = TempPath::dir();
+        ["b.mid", "a.MID", "c.kar", "notes.txt"]
+            .iter()
+            .for_each(|name| fs::write(dir.0.join(name), b"").unwrap());
+
+        let opts = |args: &[&str]| dir.opts(&[&["sample.mid"], args].concat());
+        let mut playlist = Playlist::from_options(&opts(&[])).unwrap();
+        let names: Vec<String> = (0..playlist.len())
+            .map(|index| {
+                playlist.set_current(index);
+                playlist.name()
+            })
+            .collect();
+        assert_eq!(names, vec!["a.MID", "b.mid", "c.kar", "sample.mid"]);
+        assert_eq!(playlist.current(), 3);
+        assert!(playlist.load().is_ok());
+        // Stops at either end, unless repeating.
+        assert_eq!(playlist.step(3, true), None);
+        assert_eq!(playlist.step(0, false), None);
+        assert_eq!(playlist.step(0, true), Some(1));
+
+        let mut playlist = Playlist::from_options(&opts(&["--repeat"])).unwrap();
+        assert_eq!(playlist.step(3, true), Some(0));
+        assert_eq!(playlist.step(0, false), Some(3));
+        playlist.set_current(0);
+        assert!(matches!(playlist.load(), Err(MidiError::Parse { .. })));
+
+        let empty = TempPath::dir();
+        assert!(matches!(
+            Playlist::from_options(&empty.opts(&[])),
+            Err(MidiError::NoMidiFiles { .. })
+        ));
+    }
+
+    #[test]
+    fn playlist_shuffle() {
+        let mut items: Vec<usize> = (0..20).collect();
+        shuffle(&mut items, 42);
+        assert_ne!(items, (0..20).collect::<Vec<usize>>());
+        items.sort();
+        assert_eq!(items, (0..20).collect::<Vec<usize>>());
+    }
+}
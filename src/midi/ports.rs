@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::error::{MidiError, Result};
+use midir::{MidiInput, MidiOutput, MidiOutputConnection};
+
+/// A port as seen by `--list-ports`.
+struct PortInfo {
+    name: String,
+    id: String,
+}
+
+/// Format ports as tab separated `direction index id name` lines.
+///
+/// The name comes last since it may contain spaces.
+fn format_ports(direction: &str, ports: &[PortInfo]) -> Vec<String> {
+    ports
+        .iter()
+        .enumerate()
+        .map(|(index, port)| format!("{}\t{}\t{}\t{}", direction, index, port.id, port.name))
+        .collect()
+}
+
+fn input_ports() -> Result<Vec<PortInfo>> {
+    let midi_in = MidiInput::new("mirmidivi-rs").map_err(|e| MidiError::Backend(e.to_string()))?;
+    Ok(midi_in
+        .ports()
+        .iter()
+        .map(|port| PortInfo {
+            name: midi_in.port_name(port).unwrap_or_default(),
+            id: port.id(),
+        })
+        .collect())
+}
+
+fn output_ports() -> Result<Vec<PortInfo>> {
+    let midi_out =
+        MidiOutput::new("mirmidivi-rs").map_err(|e| MidiError::Backend(e.to_string()))?;
+    Ok(midi_out
+        .ports()
+        .iter()
+        .map(|port| PortInfo {
+            name: midi_out.port_name(port).unwrap_or_default(),
+            id: port.id(),
+        })
+        .collect())
+}
+
+/// Find the `direction` port matching `spec` in `names`.
+///
+/// `spec` is either an index into `names` or a case-insensitive substring of
+/// a port name. Without `spec` the first port is used.
+pub fn select_port(direction: &'static str, names: &[String], spec: Option<&str>) -> Result<usize> {
+    if names.is_empty() {
+        return Err(MidiError::NoPorts { direction });
+    }
+
+    let spec = match spec {
+        Some(spec) => spec,
+        None => return Ok(0),
+    };
+
+    if let Ok(index) = spec.parse::<usize>() {
+        if index < names.len() {
+            return Ok(index);
+        }
+        return Err(MidiError::PortOutOfRange {
+            direction,
+            index,
+            count: names.len(),
+        });
+    }
+
+    let needle = spec.to_lowercase();
+    match names
+        .iter()
+        .position(|name| name.to_lowercase().contains(&needle))
+    {
+        Some(index) => Ok(index),
+        None => Err(MidiError::NoMatchingPort {
+            direction,
+            spec: spec.to_owned(),
+            available: names.to_vec(),
+        }),
+    }
+}
+
+/// Select a port for every `spec`, or the first port when there is none.
+pub fn select_ports(
+    direction: &'static str,
+    names: &[String],
+    specs: &[String],
+) -> Result<Vec<usize>> {
+    if specs.is_empty() {
+        return Ok(vec![select_port(direction, names, None)?]);
+    }
+    specs
+        .iter()
+        .map(|spec| select_port(direction, names, Some(spec)))
+        .collect()
+}
+
+/// Connect to the output port matching `spec`, as for [`select_port`].
+pub fn connect_output(spec: &str) -> Result<MidiOutputConnection> {
+    let names: Vec<String> = output_ports()?.into_iter().map(|port| port.name).collect();
+    let index = select_port("output", &names, Some(spec))?;
+    let midi_out =
+        MidiOutput::new("mirmidivi-rs").map_err(|e| MidiError::Backend(e.to_string()))?;
+    let out_port = midi_out.ports().get(index).cloned().ok_or_else(|| {
+        MidiError::Backend(format!("MIDI output port {} disappeared", names[index]))
+    })?;
+    midi_out
+        .connect(&out_port, &names[index])
+        .map_err(|e| MidiError::Backend(format!("Failed to connect to {}: {}", names[index], e)))
+}
+
+/// Print every MIDI input and output port to stdout.
+pub fn list_ports() -> Result<()> {
+    format_ports("input", &input_ports()?)
+        .into_iter()
+        .chain(format_ports("output", &output_ports()?))
+        .for_each(|line| println!("{}", line));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_ports, select_port, select_ports, PortInfo};
+
+    fn port_names() -> Vec<String> {
+        vec![
+            "Midi Through:Midi Through Port-0 14:0".to_owned(),
+            "KeyStation 88:KeyStation 88 MIDI 1 20:0".to_owned(),
+            "nanoPAD2:nanoPAD2 MIDI 1 24:0".to_owned(),
+        ]
+    }
+
+    #[test]
+    fn format_ports_lines() {
+        let ports = [
+            PortInfo {
+                name: "Midi Through:Midi Through Port-0 14:0".to_owned(),
+                id: "14:0".to_owned(),
+            },
+            PortInfo {
+                name: "nanoPAD2:nanoPAD2 MIDI 1 24:0".to_owned(),
+                id: "24:0".to_owned(),
+            },
+        ];
+        assert_eq!(
+            format_ports("input", &ports),
+            vec![
+                "input\t0\t14:0\tMidi Through:Midi Through Port-0 14:0",
+                "input\t1\t24:0\tnanoPAD2:nanoPAD2 MIDI 1 24:0",
+            ]
+        );
+    }
+
+    #[test]
+    fn select_port_default() {
+        assert_eq!(select_port("input", &port_names(), None).unwrap(), 0);
+    }
+
+    #[test]
+    fn select_port_by_index() {
+        assert_eq!(select_port("input", &port_names(), Some("2")).unwrap(), 2);
+        assert!(select_port("input", &port_names(), Some("3")).is_err());
+    }
+
+    #[test]
+    fn select_port_by_name() {
+        assert_eq!(
+            select_port("input", &port_names(), Some("keystation")).unwrap(),
+            1
+        );
+        assert_eq!(
+            select_port("input", &port_names(), Some("NANOPAD")).unwrap(),
+            2
+        );
+        assert!(select_port("input", &port_names(), Some("launchpad")).is_err());
+    }
+
+    #[test]
+    fn select_ports_merged() {
+        assert_eq!(select_ports("input", &port_names(), &[]).unwrap(), vec![0]);
+        assert_eq!(
+            select_ports(
+                "input",
+                &port_names(),
+                &["nanopad".to_owned(), "1".to_owned()]
+            )
+            .unwrap(),
+            vec![2, 1]
+        );
+        assert!(select_ports(
+            "input",
+            &port_names(),
+            &["1".to_owned(), "launchpad".to_owned()]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn select_port_no_ports() {
+        assert!(select_port("input", &[], None).is_err());
+        assert!(select_port("input", &[], Some("0")).is_err());
+    }
+}
@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use nodi::midly::{
+    live::{LiveEvent, SystemCommon},
+    Arena, Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use time::Duration;
+
+use super::clock::Clock;
+use crate::{options::Options, Message, MidiData, Notice, SourceId};
+
+/// Tempos recordings can be written at, in beats per minute. A Standard
+/// MIDI File holds the length of a beat in 24 bits of microseconds, so it
+/// cannot go much below 4.
+pub const TEMPOS: RangeInclusive<f64> = 4.0..=960.0;
+
+/// Ticks per quarter note recordings can be written with, which a Standard
+/// MIDI File holds in 15 bits.
+pub const PPQS: RangeInclusive<i64> = 1..=32767;
+
+/// How recorded messages are split into tracks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    /// A track per MIDI channel, and one for system exclusive messages
+    Channel,
+    /// A track per input port
+    Source,
+}
+
+/// How recordings are written.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Ticks per quarter note
+    pub ppq: u16,
+    /// Microseconds per quarter note
+    pub tempo: u32,
+    pub split: Split,
+}
+
+/// Records live MIDI into Format 1 Standard MIDI Files, a file per take.
+///
+/// Takes after the first are numbered: `take.mid`, `take-2.mid` and so on.
+/// Cheap to clone, so renderers can keep one for their key bindings.
+#[derive(Clone)]
+pub struct Recorder {
+    clock: Clock,
+    notice_send: Sender<Notice>,
+    shared: Arc<Mutex<State>>,
+}
+
+struct State {
+    /// Where the first take goes
+    path: PathBuf,
+    settings: Settings,
+    /// Takes started so far
+    takes: usize,
+    take: Option<Take>,
+}
+
+struct Take {
+    path: PathBuf,
+    start: Duration,
+    messages: Vec<(Duration, SourceId, Message)>,
+}
+
+impl Recorder {
+    pub fn new(
+        path: PathBuf,
+        settings: Settings,
+        clock: Clock,
+        notice_send: Sender<Notice>,
+    ) -> Self {
+        Self {
+            clock,
+            notice_send,
+            shared: Arc::new(Mutex::new(State {
+                path,
+                settings,
+                takes: 0,
+                take: None,
+            })),
+        }
+    }
+
+    /// Record as `opts` say, starting at once if `--record` is given.
+    /// Otherwise takes go to a file named after the time.
+    pub fn from_options(opts: &Options, clock: Clock, notice_send: Sender<Notice>) -> Self {
+        let path = opts.record.clone().unwrap_or_else(|| {
+            let since = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            format!("mirmidivi-rs-{}.mid", since)
+        });
+        let settings = Settings {
+            ppq: opts.record_ppq,
+            tempo: (60_000_000.0 / opts.record_tempo).round() as u32,
+            split: match opts.record_tracks.as_str() {
+                "port" => Split::Source,
+                _ => Split::Channel,
+            },
+        };
+        let recorder = Self::new(PathBuf::from(path), settings, clock, notice_send);
+        if opts.record.is_some() {
+            recorder.start();
+        }
+        recorder
+    }
+
+    /// Start a new take, unless recording already.
+    pub fn start(&self) {
+        let mut state = self.shared.lock().unwrap();
+        if state.take.is_some() {
+            return;
+        }
+        state.takes += 1;
+        let path = take_path(&state.path, state.takes);
+        let _send = self.notice_send.send(Notice::Recording {
+            path: path.display().to_string(),
+        });
+        state.take = Some(Take {
+            path,
+            start: self.clock.now(),
+            messages: Vec::new(),
+        });
+    }
+
+    /// End the take, if any, and write it.
+    pub fn stop(&self) {
+        let mut state = self.shared.lock().unwrap();
+        let take = match state.take.take() {
+            Some(take) => take,
+            None => return,
+        };
+        let smf = encode(&take, self.clock.now(), &state.settings);
+        let error = fs::write(&take.path, smf).err().map(|e| e.to_string());
+        let _send = self.notice_send.send(Notice::Recorded {
+            path: take.path.display().to_string(),
+            error,
+        });
+    }
+
+    pub fn toggle(&self) {
+        match self.is_recording() {
+            true => self.stop(),
+            false => self.start(),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.shared.lock().unwrap().take.is_some()
+    }
+
+    fn record(&self, midi: &MidiData) {
+        if let Some(take) = &mut self.shared.lock().unwrap().take {
+            take.messages
+                .push((midi.timestamp, midi.source, midi.message.clone()));
+        }
+    }
+
+    /// Pass on the messages from `midi_recv`, recording them meanwhile.
+    ///
+    /// Once `midi_recv` disconnects, the take is written and the returned
+    /// receiver disconnects too.
+    pub fn tap(&self, midi_recv: Receiver<MidiData>) -> (Receiver<MidiData>, JoinHandle<()>) {
+        let (midi_send, tapped_recv) = unbounded();
+        let recorder = self.clone();
+        let handler = thread::spawn(move || {
+            midi_recv.iter().for_each(|midi| {
+                recorder.record(&midi);
+                let _send = midi_send.send(midi);
+            });
+            recorder.stop();
+        });
+        (tapped_recv, handler)
+    }
+}
+
+/// `path` for the first take, numbered before the extension for the others.
+fn take_path(path: &Path, take: usize) -> PathBuf {
+    if take <= 1 {
+        return path.to_owned();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, take, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, take),
+    };
+    path.with_file_name(name)
+}
+
+/// A Format 1 file of `take` up to `end`: a tempo track, then a track per
+/// channel or port. Channel messages and system exclusive messages are
+/// kept; clock and other system messages are not.
+fn encode(take: &Take, end: Duration, settings: &Settings) -> Vec<u8> {
+    let tick = |time: Duration| {
+        let micros = (time - take.start).whole_microseconds().max(0);
+        (micros * settings.ppq as i128 / settings.tempo as i128) as u32
+    };
+    let arena = Arena::new();
+    // Events of each track by (kind, number), and where each track is.
+    let mut tracks: BTreeMap<(u8, usize), (Vec<TrackEvent>, u32)> = BTreeMap::new();
+    take.messages
+        .iter()
+        .filter(|(time, ..)| *time >= take.start)
+        .for_each(|(time, source, message)| {
+            let event = match LiveEvent::parse(message) {
+                Ok(event @ LiveEvent::Midi { .. })
+                | Ok(event @ LiveEvent::Common(SystemCommon::SysEx(_))) => event,
+                _ => return,
+            };
+            let key = match (settings.split, event) {
+                (Split::Source, _) => (0, *source),
+                (Split::Channel, LiveEvent::Midi { channel, .. }) => {
+                    (0, u8::from(channel) as usize)
+                }
+                (Split::Channel, _) => (1, 0),
+            };
+            let (events, last) = tracks.entry(key).or_default();
+            // Ports stamp their messages apart, so they may come a bit late.
+            let now = tick(*time).max(*last);
+            events.push(TrackEvent {
+                delta: (now - *last).into(),
+                kind: event.as_track_event(&arena),
+            });
+            *last = now;
+        });
+
+    let meta = |delta: u32, message| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Meta(message),
+    };
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(settings.ppq.into()),
+    ));
+    smf.tracks.push(vec![
+        meta(0, MetaMessage::Tempo(settings.tempo.into())),
+        meta(0, MetaMessage::TimeSignature(4, 2, 24, 8)),
+        meta(tick(end), MetaMessage::EndOfTrack),
+    ]);
+    let names: Vec<String> = tracks
+        .keys()
+        .map(|&(kind, number)| match (settings.split, kind) {
+            (Split::Source, _) => format!("Input {}", number + 1),
+            (Split::Channel, 0) => format!("Channel {}", number + 1),
+            (Split::Channel, _) => "System exclusive".to_owned(),
+        })
+        .collect();
+    tracks
+        .into_values()
+        .zip(&names)
+        .for_each(|((events, _), name)| {
+            let mut track = vec![meta(0, MetaMessage::TrackName(name.as_bytes()))];
+            track.extend(events);
+            track.push(meta(0, MetaMessage::EndOfTrack));
+            smf.tracks.push(track);
+        });
+
+    let mut file = Vec::new();
+    smf.write_std(&mut file).unwrap();
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use clap::Parser;
+    use crossbeam_channel::unbounded;
+    use nodi::midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+    use time::Duration;
+
+    use super::{encode, take_path, Settings, Split, Take};
+    use crate::midi::test_util::TempPath;
+    use crate::options::Options;
+
+    fn take() -> Take {
+        let ms = Duration::milliseconds;
+        Take {
+            path: "take.mid".into(),
+            start: ms(1000),
+            messages: vec![
+                // Before the take started.
+                (ms(900), 0, vec![0x90, 59, 100]),
+                (ms(1000), 0, vec![0x90, 60, 100]),
+                (ms(1250), 1, vec![0x99, 36, 127]),
+                (ms(1250), 1, vec![0xF8]),
+                (ms(1500), 0, vec![0x80, 60, 0]),
+                (ms(1500), 1, vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]),
+            ],
+        }
+    }
+
+    fn names(smf: &Smf) -> Vec<String> {
+        smf.tracks[1..]
+            .iter()
+            .map(|track| match track[0].kind {
+                TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                    String::from_utf8_lossy(name).into_owned()
+                }
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recorder_encode() {
+        let mut settings = Settings {
+            ppq: 96,
+            tempo: 500_000,
+            split: Split::Channel,
+        };
+        let file = encode(&take(), Duration::seconds(3), &settings);
+        let smf = Smf::parse(&file).unwrap();
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.header.timing, Timing::Metrical(96.into()));
+        assert_eq!(smf.tracks.len(), 4);
+        // The tempo track lasts as long as the take: four beats.
+        assert_eq!(u32::from(smf.tracks[0][2].delta), 384);
+        assert_eq!(
+            names(&smf),
+            vec!["Channel 1", "Channel 10", "System exclusive"]
+        );
+        let channel = &smf.tracks[1];
+        assert_eq!(channel.len(), 4);
+        assert!(matches!(
+            channel[1].kind,
+            TrackEventKind::Midi { message: MidiMessage::NoteOn { key, .. }, .. } if key == 60
+        ));
+        // A beat later.
+        assert_eq!(u32::from(channel[2].delta), 96);
+        assert!(matches!(
+            smf.tracks[3][1].kind,
+            TrackEventKind::SysEx(data) if data == [0x7E, 0x7F, 0x09, 0x01, 0xF7]
+        ));
+
+        settings.split = Split::Source;
+        let file = encode(&take(), Duration::seconds(3), &settings);
+        let smf = Smf::parse(&file).unwrap();
+        assert_eq!(names(&smf), vec!["Input 1", "Input 2"]);
+        assert_eq!(smf.tracks[2].len(), 4);
+    }
+
+    #[test]
+    fn recorder_options() {
+        let parse = |args: &[&str]| Options::try_parse_from(["mirmidivi-rs"].iter().chain(args));
+        let opts = parse(&["--record-ppq", "32767", "--record-tempo", "4"]).unwrap();
+        assert_eq!(opts.record_ppq, 32767);
+        assert_eq!(opts.record_tempo, 4.0);
+        // A beat too long for the file, or none at all.
+        for tempo in ["3.5", "0", "-120", "NaN"] {
+            assert!(parse(&["--record-tempo", tempo]).is_err());
+        }
+        for ppq in ["0", "32768"] {
+            assert!(parse(&["--record-ppq", ppq]).is_err());
+        }
+    }
+
+    #[test]
+    fn recorder_takes() {
+        let dir = TempPath::dir();
+        let settings = Settings {
+            ppq: 480,
+            tempo: 500_000,
+            split: Split::Channel,
+        };
+        let (notice_send, _notice_recv) = unbounded();
+        let recorder = super::Recorder::new(
+            dir.0.join("take.mid"),
+            settings,
+            crate::midi::Clock::new(),
+            notice_send,
+        );
+        let (midi_send, midi_recv) = unbounded();
+        let (tapped_recv, handler) = recorder.tap(midi_recv);
+
+        recorder.toggle();
+        let send = |message: Vec<u8>| {
+            midi_send
+                .send(crate::MidiData {
+                    message,
+                    timestamp: Duration::seconds(1_000),
+                    source: 0,
+                })
+                .unwrap();
+        };
+        send(vec![0x90, 60, 100]);
+        assert_eq!(tapped_recv.recv().unwrap().message, vec![0x90, 60, 100]);
+        recorder.toggle();
+        assert!(!recorder.is_recording());
+        recorder.start();
+        send(vec![0x90, 62, 100]);
+        // Disconnecting writes the take being recorded.
+        drop(midi_send);
+        handler.join().unwrap();
+        assert!(tapped_recv.recv().is_ok());
+        assert!(tapped_recv.recv().is_err());
+
+        let smf = |name: &str| {
+            let file = std::fs::read(dir.0.join(name)).unwrap();
+            Smf::parse(&file).map(|smf| smf.tracks.len()).unwrap()
+        };
+        assert_eq!(smf("take.mid"), 2);
+        assert_eq!(smf("take-2.mid"), 2);
+        assert_eq!(take_path(Path::new("dir/take"), 3), Path::new("dir/take-3"));
+    }
+}
@@ -0,0 +1,379 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The RTP-MIDI wire format (RFC 6295), and the AppleMIDI session protocol
+//! that sets up RTP-MIDI sessions.
+
+use super::stream_parser::length;
+use crate::Message;
+
+/// Starts every session packet, where RTP packets have their version.
+const SIGNATURE: [u8; 2] = [0xFF, 0xFF];
+/// Version of the session protocol
+const VERSION: u32 = 2;
+
+/// A packet of the AppleMIDI session protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionPacket {
+    /// `IN`: asks to join the session, first on the control port, then on
+    /// the data port
+    Invitation { token: u32, ssrc: u32, name: String },
+    /// `OK`: the invitation is accepted
+    Accepted { token: u32, ssrc: u32, name: String },
+    /// `NO`: the invitation is rejected
+    Rejected { token: u32, ssrc: u32 },
+    /// `BY`: leaves the session
+    End { token: u32, ssrc: u32 },
+    /// `CK`: clock synchronization, timestamps in units of 100 microseconds.
+    /// The initiator sends `count` 0, the responder answers with 1, and the
+    /// initiator ends with 2, each adding its timestamp
+    Sync {
+        ssrc: u32,
+        count: u8,
+        timestamps: [u64; 3],
+    },
+    /// `RS`: everything up to `sequence` has been received
+    Feedback { ssrc: u32, sequence: u16 },
+}
+
+impl SessionPacket {
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.get(..2)? != SIGNATURE {
+            return None;
+        }
+        let u32_at = |at: usize| Some(u32::from_be_bytes(packet.get(at..at + 4)?.try_into().ok()?));
+        let u64_at = |at: usize| Some(u64::from_be_bytes(packet.get(at..at + 8)?.try_into().ok()?));
+        let name = || {
+            let name = packet.get(16..).unwrap_or_default();
+            let end = name
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(name.len());
+            String::from_utf8_lossy(&name[..end]).into_owned()
+        };
+        match packet.get(2..4)? {
+            b"IN" => Some(SessionPacket::Invitation {
+                token: u32_at(8)?,
+                ssrc: u32_at(12)?,
+                name: name(),
+            }),
+            b"OK" => Some(SessionPacket::Accepted {
+                token: u32_at(8)?,
+                ssrc: u32_at(12)?,
+                name: name(),
+            }),
+            b"NO" => Some(SessionPacket::Rejected {
+                token: u32_at(8)?,
+                ssrc: u32_at(12)?,
+            }),
+            b"BY" => Some(SessionPacket::End {
+                token: u32_at(8)?,
+                ssrc: u32_at(12)?,
+            }),
+            b"CK" => Some(SessionPacket::Sync {
+                ssrc: u32_at(4)?,
+                count: *packet.get(8)?,
+                timestamps: [u64_at(12)?, u64_at(20)?, u64_at(28)?],
+            }),
+            b"RS" => Some(SessionPacket::Feedback {
+                ssrc: u32_at(4)?,
+                sequence: (u32_at(8)? >> 16) as u16,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut packet = SIGNATURE.to_vec();
+        let mut handshake = |command: &[u8], token: u32, ssrc: u32, name: Option<&str>| {
+            packet.extend_from_slice(command);
+            packet.extend_from_slice(&VERSION.to_be_bytes());
+            packet.extend_from_slice(&token.to_be_bytes());
+            packet.extend_from_slice(&ssrc.to_be_bytes());
+            if let Some(name) = name {
+                packet.extend_from_slice(name.as_bytes());
+                packet.push(0);
+            }
+        };
+        match self {
+            SessionPacket::Invitation { token, ssrc, name } => {
+                handshake(b"IN", *token, *ssrc, Some(name))
+            }
+            SessionPacket::Accepted { token, ssrc, name } => {
+                handshake(b"OK", *token, *ssrc, Some(name))
+            }
+            SessionPacket::Rejected { token, ssrc } => handshake(b"NO", *token, *ssrc, None),
+            SessionPacket::End { token, ssrc } => handshake(b"BY", *token, *ssrc, None),
+            SessionPacket::Sync {
+                ssrc,
+                count,
+                timestamps,
+            } => {
+                packet.extend_from_slice(b"CK");
+                packet.extend_from_slice(&ssrc.to_be_bytes());
+                packet.extend_from_slice(&[*count, 0, 0, 0]);
+                timestamps
+                    .iter()
+                    .for_each(|timestamp| packet.extend_from_slice(&timestamp.to_be_bytes()));
+            }
+            SessionPacket::Feedback { ssrc, sequence } => {
+                packet.extend_from_slice(b"RS");
+                packet.extend_from_slice(&ssrc.to_be_bytes());
+                packet.extend_from_slice(&sequence.to_be_bytes());
+                packet.extend_from_slice(&[0, 0]);
+            }
+        }
+        packet
+    }
+}
+
+/// The sender and MIDI command section of an RTP-MIDI packet.
+pub struct RtpPacket<'a> {
+    pub ssrc: u32,
+    /// Whether the first command has a delta time
+    delta_first: bool,
+    /// The MIDI list: commands, each but the first after a delta time
+    commands: &'a [u8],
+}
+
+impl<'a> RtpPacket<'a> {
+    /// The MIDI command section of `packet`, without the recovery journal
+    /// after it.
+    pub fn parse(packet: &'a [u8]) -> Option<Self> {
+        // RTP version 2; the header goes on with any contributing sources.
+        let first = *packet.first()?;
+        if first & 0xC0 != 0x80 {
+            return None;
+        }
+        let ssrc = u32::from_be_bytes(packet.get(8..12)?.try_into().ok()?);
+        let at = 12 + 4 * (first & 0x0F) as usize;
+        let flags = *packet.get(at)?;
+        let (start, len) = match flags & 0x80 {
+            0 => (at + 1, (flags & 0x0F) as usize),
+            _ => (
+                at + 2,
+                ((flags & 0x0F) as usize) << 8 | *packet.get(at + 1)? as usize,
+            ),
+        };
+        Some(Self {
+            ssrc,
+            delta_first: flags & 0x20 != 0,
+            commands: packet.get(start..start + len)?,
+        })
+    }
+}
+
+/// Turns the MIDI lists of a sender's packets into messages.
+///
+/// Running status carries over from packet to packet, and so do system
+/// exclusive messages sent in segments (`F0 … F0`, `F7 … F0`, `F7 … F7`).
+/// Lost packets are not recovered: their commands are just missing.
+#[derive(Default)]
+pub struct Decoder {
+    running: Option<u8>,
+    /// The segments of a system exclusive message so far
+    sysex: Message,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The messages of `packet`, each with its delta time from the packet's
+    /// timestamp, in RTP timestamp units. A malformed command ends the list.
+    pub fn decode(&mut self, packet: &RtpPacket) -> Vec<(u32, Message)> {
+        let list = packet.commands;
+        let mut messages = Vec::new();
+        let (mut at, mut time) = (0, 0);
+        while at < list.len() {
+            if at > 0 || packet.delta_first {
+                match delta_time(&list[at..]) {
+                    Some((delta, read)) => {
+                        time += delta;
+                        at += read;
+                    }
+                    None => break,
+                }
+            }
+            // With running status, the status byte is left out.
+            let (status, data) = match (list.get(at), self.running) {
+                (Some(byte @ 0x80..), _) => (*byte, at + 1),
+                (Some(_), Some(status)) => (status, at),
+                _ => break,
+            };
+            match status {
+                0xF0 | 0xF7 => match self.segment(&list[at..], &mut messages, time) {
+                    Some(read) => at += read,
+                    None => break,
+                },
+                _ => {
+                    let end = data + length(status) - 1;
+                    match list.get(data..end) {
+                        Some(data) if data.iter().all(|byte| *byte < 0x80) => {
+                            let mut message = vec![status];
+                            message.extend_from_slice(data);
+                            messages.push((time, message));
+                            at = end;
+                        }
+                        _ => break,
+                    }
+                    match status {
+                        0x80..=0xEF => self.running = Some(status),
+                        0xF0..=0xF7 => self.running = None,
+                        _ => (),
+                    }
+                }
+            }
+        }
+        messages
+    }
+
+    /// Read a system exclusive segment from the start of `list`, returning
+    /// how many bytes it took. Real-time messages in it are passed on at
+    /// once, and the message itself once its last segment is in.
+    fn segment(
+        &mut self,
+        list: &[u8],
+        messages: &mut Vec<(u32, Message)>,
+        time: u32,
+    ) -> Option<usize> {
+        self.running = None;
+        if list[0] == 0xF0 {
+            self.sysex = vec![0xF0];
+        }
+        for (at, byte) in list.iter().enumerate().skip(1) {
+            match byte {
+                0xF8.. => messages.push((time, vec![*byte])),
+                0x00..=0x7F => self.sysex.push(*byte),
+                // More segments to come.
+                0xF0 => return Some(at + 1),
+                0xF7 => {
+                    self.sysex.push(*byte);
+                    // A last segment whose first one went missing is dropped.
+                    if self.sysex[0] == 0xF0 {
+                        messages.push((time, std::mem::take(&mut self.sysex)));
+                    }
+                    return Some(at + 1);
+                }
+                // Cancelled, or malformed.
+                _ => {
+                    self.sysex.clear();
+                    return Some(at + 1);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A delta time at the start of `list`, and how many bytes it took: up to
+/// four, seven bits each, most significant first.
+fn delta_time(list: &[u8]) -> Option<(u32, usize)> {
+    let mut delta = 0;
+    for (at, byte) in list.iter().take(4).enumerate() {
+        delta = delta << 7 | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Some((delta, at + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, RtpPacket, SessionPacket};
+
+    /// An RTP-MIDI packet from `ssrc` carrying the MIDI list `commands`,
+    /// with a journal after it.
+    fn rtp_packet(ssrc: u32, sequence: u16, flags: u8, commands: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 0x61];
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&1000u32.to_be_bytes());
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+        match commands.len() {
+            0..=15 => packet.push(flags | commands.len() as u8),
+            len => packet.extend_from_slice(&[flags | 0x80 | (len >> 8) as u8, len as u8]),
+        }
+        packet.extend_from_slice(commands);
+        packet.extend_from_slice(&[0x00, 0x07, 0x08]);
+        packet
+    }
+
+    #[test]
+    fn rtp_midi_session_packets() {
+        let packets = [
+            SessionPacket::Invitation {
+                token: 0x1234,
+                ssrc: 0xCAFE,
+                name: "Session 1".to_owned(),
+            },
+            SessionPacket::End { token: 1, ssrc: 2 },
+            SessionPacket::Sync {
+                ssrc: 3,
+                count: 1,
+                timestamps: [4, 5, 0],
+            },
+            SessionPacket::Feedback {
+                ssrc: 6,
+                sequence: 7,
+            },
+        ];
+        packets.iter().for_each(|packet| {
+            assert_eq!(
+                SessionPacket::parse(&packet.to_bytes()).as_ref(),
+                Some(packet)
+            );
+        });
+        assert_eq!(&packets[0].to_bytes()[..4], b"\xFF\xFFIN");
+        assert_eq!(packets[2].to_bytes().len(), 36);
+        assert_eq!(SessionPacket::parse(b"\xFF\xFFXX"), None);
+    }
+
+    #[test]
+    fn rtp_midi_decode() {
+        let mut decoder = Decoder::new();
+        // Running status, a delta time in two bytes, and real time amid a
+        // system exclusive message.
+        let packet = rtp_packet(
+            9,
+            1,
+            0x40,
+            &[
+                0x90, 60, 100, 0, 64, 100, 0x81, 0x00, 0xF0, 0x7E, 0xF8, 0x7F, 0xF7, 1, 0xB0, 7, 90,
+            ],
+        );
+        let packet = RtpPacket::parse(&packet).unwrap();
+        assert_eq!(packet.ssrc, 9);
+        assert_eq!(
+            decoder.decode(&packet),
+            vec![
+                (0, vec![0x90, 60, 100]),
+                (0, vec![0x90, 64, 100]),
+                (128, vec![0xF8]),
+                (128, vec![0xF0, 0x7E, 0x7F, 0xF7]),
+                (129, vec![0xB0, 7, 90]),
+            ]
+        );
+
+        // Running status left over, and a system exclusive message in
+        // segments across packets.
+        let packet = rtp_packet(9, 2, 0x20, &[5, 10, 0, 0, 0xF0, 0x01, 0xF0]);
+        assert_eq!(
+            decoder.decode(&RtpPacket::parse(&packet).unwrap()),
+            vec![(5, vec![0xB0, 10, 0])]
+        );
+        let packet = rtp_packet(9, 3, 0x00, &[0xF7, 0x02, 0xF0, 0, 0xF7, 0x03, 0xF7]);
+        assert_eq!(
+            decoder.decode(&RtpPacket::parse(&packet).unwrap()),
+            vec![(0, vec![0xF0, 0x01, 0x02, 0x03, 0xF7])]
+        );
+        // Cancelled.
+        let packet = rtp_packet(9, 4, 0x00, &[0xF0, 0x01, 0xF0, 0, 0xF7, 0xF4]);
+        assert!(decoder
+            .decode(&RtpPacket::parse(&packet).unwrap())
+            .is_empty());
+
+        assert!(RtpPacket::parse(&[0x80, 0x61]).is_none());
+        assert!(RtpPacket::parse(&[0xFF, 0xFF, b'C', b'K']).is_none());
+    }
+}
@@ -0,0 +1,461 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration as StdDuration, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
+use time::Duration;
+
+use super::clock::Clock;
+use super::error::{MidiError, Result};
+use super::midi_in::tap;
+use super::mixer::Mixer;
+use super::playback::Transport;
+use super::recorder::Recorder;
+use super::rtp_midi::{Decoder, RtpPacket, SessionPacket};
+use super::song::Score;
+use super::thru::Thru;
+use crate::{midi::MidiProvider, options::Options, MidiData, Notice, SourceId};
+
+/// What this participant is called in sessions.
+const NAME: &str = "mirmidivi-rs";
+/// How often the sockets stop waiting to see whether to close.
+const POLL_INTERVAL: StdDuration = StdDuration::from_millis(100);
+/// Length of an RTP timestamp unit, and of a clock synchronization one.
+const TIMESTAMP_UNIT: Duration = Duration::microseconds(100);
+
+/// MIDI from RTP-MIDI (AppleMIDI) sessions, as set up by macOS Audio MIDI
+/// Setup, rtpMIDI or rtpmidid.
+///
+/// Listens on a control port and the data port after it, accepting every
+/// invitation and answering clock synchronization. Each participant is a
+/// source, numbered in the order they first joined. The recovery journal
+/// is ignored, so messages in lost packets are lost.
+pub struct RtpMidiIn {
+    midi_recv: Receiver<MidiData>,
+    notice_recv: Receiver<Notice>,
+    clock: Clock,
+    /// Dropped to leave the sessions
+    stop_send: Option<Sender<()>>,
+    /// Serve the control and data ports
+    handlers: Vec<JoinHandle<()>>,
+    recorder: Recorder,
+    /// Mutes and solos channels
+    mixer: Mixer,
+    /// Pass the messages on to `midi_recv`; see [`tap`]
+    taps: Vec<JoinHandle<()>>,
+}
+
+impl MidiProvider for RtpMidiIn {
+    fn get_midi_in_recv(&self) -> Receiver<MidiData> {
+        self.midi_recv.clone()
+    }
+
+    fn get_notice_recv(&self) -> Receiver<Notice> {
+        self.notice_recv.clone()
+    }
+
+    fn get_clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    fn get_transport(&self) -> Option<Transport> {
+        None
+    }
+
+    fn get_recorder(&self) -> Option<Recorder> {
+        Some(self.recorder.clone())
+    }
+
+    fn get_mixer(&self) -> Option<Mixer> {
+        Some(self.mixer.clone())
+    }
+
+    fn get_score(&self) -> Option<Arc<Score>> {
+        None
+    }
+
+    fn new(opts: &Options) -> Result<Self> {
+        // The usual port, should it not be given. The options leave room
+        // for the data port after it.
+        let port = opts.rtp_midi.unwrap_or(5004);
+        let control = bind(port)?;
+        let data = bind(port + 1)?;
+        Self::with_sockets(opts, control, data)
+    }
+}
+
+/// A UDP socket on `port` of every interface.
+pub(super) fn bind(port: u16) -> Result<UdpSocket> {
+    let address = format!("0.0.0.0:{}", port);
+    UdpSocket::bind(&address).map_err(|source| MidiError::Socket { address, source })
+}
+
+impl RtpMidiIn {
+    fn with_sockets(opts: &Options, control: UdpSocket, data: UdpSocket) -> Result<Self> {
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let clock = Clock::new();
+        let mixer = Mixer::new();
+        let (recorder, midi_recv, taps) = tap(opts, &clock, &notice_send, &mixer, midi_recv)?;
+
+        let session = Arc::new(Mutex::new(Session {
+            ssrc: ssrc(),
+            participants: HashMap::new(),
+            sources: Vec::new(),
+            midi_send,
+            notice_send,
+            thru: Thru::from_options(opts)?.map(|thru| thru.with_mixer(mixer.clone())),
+            clock: clock.clone(),
+        }));
+        let (stop_send, stop_recv) = bounded(0);
+        let handlers = [(control, Port::Control), (data, Port::Data)]
+            .into_iter()
+            .map(|(socket, port)| {
+                let session = session.clone();
+                let stop_recv = stop_recv.clone();
+                socket
+                    .set_read_timeout(Some(POLL_INTERVAL))
+                    .map_err(|e| MidiError::Backend(e.to_string()))?;
+                Ok(thread::spawn(move || {
+                    serve(socket, port, session, stop_recv)
+                }))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(RtpMidiIn {
+            midi_recv,
+            notice_recv,
+            clock,
+            stop_send: Some(stop_send),
+            handlers,
+            recorder,
+            mixer,
+            taps,
+        })
+    }
+
+    /// Leave the sessions, and write what is being recorded or logged.
+    pub fn close(&mut self) {
+        self.stop_send.take();
+        self.handlers
+            .drain(..)
+            .chain(self.taps.drain(..))
+            .for_each(|handler| {
+                let _ = handler.join();
+            });
+    }
+}
+
+impl Drop for RtpMidiIn {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Port {
+    Control,
+    Data,
+}
+
+/// Someone who was invited in.
+struct Participant {
+    name: String,
+    source: SourceId,
+    /// Where to say goodbye to
+    control: SocketAddr,
+    /// Invited on the data port too, so MIDI may come
+    joined: bool,
+    decoder: Decoder,
+}
+
+struct Session {
+    /// Ours
+    ssrc: u32,
+    participants: HashMap<u32, Participant>,
+    /// SSRC of every participant so far, by source
+    sources: Vec<u32>,
+    midi_send: Sender<MidiData>,
+    notice_send: Sender<Notice>,
+    thru: Option<Thru>,
+    clock: Clock,
+}
+
+/// Answer what comes to `socket` until `stop` fires or is dropped, then
+/// say goodbye from the control port.
+fn serve(socket: UdpSocket, port: Port, session: Arc<Mutex<Session>>, stop: Receiver<()>) {
+    let mut buffer = [0; 1500];
+    while let Err(TryRecvError::Empty) = stop.try_recv() {
+        // Timeouts and errors alike just go round again.
+        if let Ok((read, from)) = socket.recv_from(&mut buffer) {
+            let replies = session
+                .lock()
+                .unwrap()
+                .on_packet(&buffer[..read], from, port);
+            replies.into_iter().for_each(|reply| {
+                let _ = socket.send_to(&reply.to_bytes(), from);
+            });
+        }
+    }
+    if port == Port::Control {
+        let session = session.lock().unwrap();
+        session.participants.values().for_each(|participant| {
+            let end = SessionPacket::End {
+                token: 0,
+                ssrc: session.ssrc,
+            };
+            let _ = socket.send_to(&end.to_bytes(), participant.control);
+        });
+    }
+}
+
+impl Session {
+    /// Take in a packet from `from`, returning the answers to it.
+    fn on_packet(&mut self, packet: &[u8], from: SocketAddr, port: Port) -> Vec<SessionPacket> {
+        match SessionPacket::parse(packet) {
+            Some(SessionPacket::Invitation { token, ssrc, name }) => {
+                self.invited(ssrc, name, from, port);
+                vec![SessionPacket::Accepted {
+                    token,
+                    ssrc: self.ssrc,
+                    name: NAME.to_owned(),
+                }]
+            }
+            Some(SessionPacket::End { ssrc, .. }) => {
+                if let Some(participant) = self.participants.remove(&ssrc) {
+                    let _send = self.notice_send.send(Notice::Disconnected {
+                        source: participant.source,
+                        port: participant.name,
+                    });
+                }
+                Vec::new()
+            }
+            Some(SessionPacket::Sync {
+                count: 0,
+                timestamps: [sent, _, _],
+                ..
+            }) => vec![SessionPacket::Sync {
+                ssrc: self.ssrc,
+                count: 1,
+                timestamps: [sent, self.timestamp(), 0],
+            }],
+            // The initiator knows the latency now; there is nothing to do
+            // with it here, as messages are timed on arrival.
+            Some(_) => Vec::new(),
+            None if port == Port::Data => {
+                self.received(packet);
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn invited(&mut self, ssrc: u32, name: String, from: SocketAddr, port: Port) {
+        let source = match self.sources.iter().position(|known| *known == ssrc) {
+            Some(source) => source,
+            None => {
+                self.sources.push(ssrc);
+                self.sources.len() - 1
+            }
+        };
+        let participant = self
+            .participants
+            .entry(ssrc)
+            .or_insert_with(|| Participant {
+                name,
+                source,
+                // Invitations come to the control port first, then the data
+                // port, which is the next one.
+                control: match port {
+                    Port::Control => from,
+                    Port::Data => SocketAddr::new(from.ip(), from.port().wrapping_sub(1)),
+                },
+                joined: false,
+                decoder: Decoder::new(),
+            });
+        if port == Port::Data && !participant.joined {
+            participant.joined = true;
+            let _send = self.notice_send.send(Notice::Connected {
+                source,
+                port: participant.name.clone(),
+            });
+        }
+    }
+
+    /// Pass on the MIDI in an RTP-MIDI packet from a participant.
+    fn received(&mut self, packet: &[u8]) {
+        let arrival = self.clock.now();
+        let packet = match RtpPacket::parse(packet) {
+            Some(packet) => packet,
+            None => return,
+        };
+        let participant = match self.participants.get_mut(&packet.ssrc) {
+            Some(participant) if participant.joined => participant,
+            _ => return,
+        };
+        for (delta, message) in participant.decoder.decode(&packet) {
+            if let Some(thru) = &self.thru {
+                thru.send(&message);
+            }
+            let _send = self.midi_send.send(MidiData {
+                message,
+                timestamp: arrival + TIMESTAMP_UNIT * delta,
+                source: participant.source,
+            });
+        }
+    }
+
+    /// Now, in clock synchronization units.
+    fn timestamp(&self) -> u64 {
+        (self.clock.now().whole_microseconds() / TIMESTAMP_UNIT.whole_microseconds()) as u64
+    }
+}
+
+/// Something different every run, to tell this participant by.
+fn ssrc() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.subsec_nanos() ^ since.as_secs() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use crossbeam_channel::RecvTimeoutError;
+
+    use super::RtpMidiIn;
+    use crate::midi::rtp_midi::SessionPacket;
+    use crate::midi::MidiProvider;
+    use crate::{Notice, Options};
+
+    /// A control port and the data port after it, on the loopback address.
+    fn socket_pair() -> (UdpSocket, UdpSocket) {
+        loop {
+            let control = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let port = match control.local_addr().unwrap().port().checked_add(1) {
+                Some(port) => port,
+                None => continue,
+            };
+            if let Ok(data) = UdpSocket::bind(("127.0.0.1", port)) {
+                return (control, data);
+            }
+        }
+    }
+
+    /// Send `packet` from `socket` to `to`, and read the answer.
+    fn ask(socket: &UdpSocket, to: &UdpSocket, packet: &SessionPacket) -> SessionPacket {
+        socket
+            .send_to(&packet.to_bytes(), to.local_addr().unwrap())
+            .unwrap();
+        let mut buffer = [0; 1500];
+        let read = socket.recv(&mut buffer).unwrap();
+        SessionPacket::parse(&buffer[..read]).unwrap()
+    }
+
+    #[test]
+    fn rtp_midi_in_session() {
+        let (control, data) = socket_pair();
+        let opts = Options::parse_from(["mirmidivi-rs"]);
+        let mut midi = RtpMidiIn::with_sockets(
+            &opts,
+            control.try_clone().unwrap(),
+            data.try_clone().unwrap(),
+        )
+        .unwrap();
+        let midi_recv = midi.get_midi_in_recv();
+        let notice_recv = midi.get_notice_recv();
+
+        // The peer, initiating the session.
+        let (peer_control, peer_data) = socket_pair();
+        [&peer_control, &peer_data].iter().for_each(|socket| {
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap()
+        });
+        let invitation = SessionPacket::Invitation {
+            token: 42,
+            ssrc: 0xBEEF,
+            name: "Peer".to_owned(),
+        };
+        for (socket, to) in [(&peer_control, &control), (&peer_data, &data)] {
+            assert!(matches!(
+                ask(socket, to, &invitation),
+                SessionPacket::Accepted { token: 42, name, .. } if name == "mirmidivi-rs"
+            ));
+        }
+        assert!(matches!(
+            notice_recv.recv_timeout(Duration::from_secs(1)),
+            Ok(Notice::Connected { source: 0, port }) if port == "Peer"
+        ));
+
+        let sync = SessionPacket::Sync {
+            ssrc: 0xBEEF,
+            count: 0,
+            timestamps: [1234, 0, 0],
+        };
+        assert!(matches!(
+            ask(&peer_data, &data, &sync),
+            SessionPacket::Sync {
+                count: 1,
+                timestamps: [1234, _, 0],
+                ..
+            }
+        ));
+
+        // A note on, then another by running status 10ms later, with a
+        // journal after them.
+        let mut packet = vec![0x80, 0x61, 0, 1, 0, 0, 0, 0, 0, 0, 0xBE, 0xEF];
+        packet.extend_from_slice(&[0x46, 0x90, 60, 100, 100, 64, 100, 0x00, 0x07, 0x08]);
+        peer_data
+            .send_to(&packet, data.local_addr().unwrap())
+            .unwrap();
+        let first = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        let second = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(first.message, vec![0x90, 60, 100]);
+        assert_eq!(second.message, vec![0x90, 64, 100]);
+        assert_eq!(second.source, 0);
+        assert_eq!(
+            second.timestamp - first.timestamp,
+            time::Duration::milliseconds(10)
+        );
+
+        let end = SessionPacket::End {
+            token: 42,
+            ssrc: 0xBEEF,
+        };
+        peer_control
+            .send_to(&end.to_bytes(), control.local_addr().unwrap())
+            .unwrap();
+        assert!(matches!(
+            notice_recv.recv_timeout(Duration::from_secs(1)),
+            Ok(Notice::Disconnected { source: 0, .. })
+        ));
+
+        midi.close();
+        assert!(matches!(
+            midi_recv.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn rtp_midi_in_ports() {
+        let parse = |args: &[&str]| Options::try_parse_from(["mirmidivi-rs"].iter().chain(args));
+        assert_eq!(parse(&["--rtp-midi"]).unwrap().rtp_midi, Some(5004));
+        assert_eq!(
+            parse(&["--rtp-midi", "65534"]).unwrap().rtp_midi,
+            Some(65534)
+        );
+        // No data port after it, or any port at all.
+        assert!(parse(&["--rtp-midi", "65535"]).is_err());
+        assert!(parse(&["--rtp-midi", "0"]).is_err());
+    }
+}
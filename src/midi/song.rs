@@ -0,0 +1,860 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use nodi::{
+    midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind},
+    timers::Ticker,
+    Event, MidiEvent, Timer,
+};
+use time::Duration;
+
+use super::error::{MidiError, Result};
+use super::timers::TimecodeTicker;
+
+/// Tempo until the file says otherwise: 120 beats per minute.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Shortest beat a time signature may have, as a power of two: a 64th note.
+/// Signatures with shorter ones are taken for corrupt and ignored.
+const MAX_DENOMINATOR: u8 = 6;
+
+/// One event of a song.
+#[derive(Debug, Clone)]
+pub struct SongEvent {
+    pub tick: u64,
+    /// SMF track the event comes from, counting from 0
+    pub track: usize,
+    /// Song time, at normal speed
+    pub time: Duration,
+    pub kind: SongEventKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum SongEventKind {
+    /// For the synthesizer
+    Midi(MidiEvent),
+    /// For the renderers only
+    Meta(Meta),
+}
+
+impl SongEventKind {
+    /// What `kind` of event on `track` matters for playing or showing the
+    /// song, if anything.
+    fn new(track: usize, kind: TrackEventKind) -> Option<Self> {
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim().to_owned();
+        let meta = match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Meta::Tempo(tempo.into()),
+            TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator, ..))
+                if denominator <= MAX_DENOMINATOR =>
+            {
+                Meta::TimeSignature(numerator, denominator)
+            }
+            TrackEventKind::Meta(MetaMessage::KeySignature(key, minor)) => {
+                Meta::KeySignature(key, minor)
+            }
+            TrackEventKind::Meta(MetaMessage::Marker(name)) => Meta::Marker(text(name)),
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) => Meta::TrackName {
+                track,
+                name: text(name),
+            },
+            // Spaces and line breaks matter in lyrics.
+            TrackEventKind::Meta(MetaMessage::Lyric(lyric)) => {
+                Meta::Lyric(String::from_utf8_lossy(lyric).into_owned())
+            }
+            TrackEventKind::Meta(MetaMessage::Text(text)) => {
+                Meta::Text(String::from_utf8_lossy(text).into_owned())
+            }
+            _ => {
+                return match Event::try_from(kind) {
+                    Ok(Event::Midi(midi)) => Some(SongEventKind::Midi(midi)),
+                    _ => None,
+                }
+            }
+        };
+        Some(SongEventKind::Meta(meta))
+    }
+}
+
+/// A note of a song, from its note on to its note off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongNote {
+    pub begin: Duration,
+    pub end: Duration,
+    pub track: usize,
+    pub channel: u8,
+    pub key: u8,
+}
+
+/// A beat of a song.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Beat {
+    pub time: Duration,
+    /// Number of the bar the beat starts, counting from 1
+    pub bar: Option<usize>,
+}
+
+/// A syllable of the lyrics of a song.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Syllable {
+    pub time: Duration,
+    pub text: String,
+    /// Whether the syllable begins a line
+    pub line: bool,
+}
+
+/// What a MIDI file says about the music, besides the notes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Meta {
+    /// Microseconds per quarter note
+    Tempo(u32),
+    /// Numerator, and denominator as a power of two
+    TimeSignature(u8, u8),
+    /// Sharps when positive or flats when negative, and whether minor
+    KeySignature(i8, bool),
+    Marker(String),
+    /// Name of an SMF track, counting from 0
+    TrackName {
+        track: usize,
+        name: String,
+    },
+    /// A syllable to sing, as it is in the file
+    Lyric(String),
+    /// Any text; karaoke files put their lyrics here
+    Text(String),
+}
+
+impl fmt::Display for Meta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MAJOR: [&str; 15] = [
+            "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+        ];
+        const MINOR: [&str; 15] = [
+            "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+        ];
+        match self {
+            Meta::Tempo(tempo) => write!(f, "{:.0} BPM", 60_000_000.0 / *tempo as f64),
+            Meta::TimeSignature(numerator, denominator) => {
+                match 1u32.checked_shl(*denominator as u32) {
+                    Some(note) => write!(f, "{}/{}", numerator, note),
+                    None => write!(f, "{}/2^{}", numerator, denominator),
+                }
+            }
+            Meta::KeySignature(key, minor) => {
+                let (names, mode) = if *minor {
+                    (MINOR, "minor")
+                } else {
+                    (MAJOR, "major")
+                };
+                match names.get((*key as i32 + 7) as usize) {
+                    Some(name) => write!(f, "{} {}", name, mode),
+                    None => write!(f, "{} {}", key, mode),
+                }
+            }
+            Meta::Marker(name) => f.write_str(name),
+            Meta::TrackName { track, name } => write!(f, "{}: {}", track + 1, name),
+            Meta::Lyric(text) | Meta::Text(text) => f.write_str(text.trim()),
+        }
+    }
+}
+
+/// What is known about a song before playing it.
+#[derive(Debug, Default)]
+pub struct Score {
+    /// Every note, ordered by start
+    pub notes: Vec<SongNote>,
+    pub beats: Vec<Beat>,
+    pub lyrics: Vec<Syllable>,
+}
+
+impl Score {
+    /// The line of lyrics being sung at `time`, and how many of its
+    /// syllables are reached; the last of them is the one sung now.
+    pub fn lyric_line(&self, time: Duration) -> Option<(&[Syllable], usize)> {
+        if self.lyrics.is_empty() {
+            return None;
+        }
+        let reached = self
+            .lyrics
+            .partition_point(|syllable| syllable.time <= time);
+        let current = reached.saturating_sub(1);
+        let begin = self.lyrics[..=current]
+            .iter()
+            .rposition(|syllable| syllable.line)
+            .unwrap_or(0);
+        let end = self.lyrics[current + 1..]
+            .iter()
+            .position(|syllable| syllable.line)
+            .map_or(self.lyrics.len(), |line| current + 1 + line);
+        Some((&self.lyrics[begin..end], reached - begin))
+    }
+}
+
+/// Tracks played together, as listed by `--list-sequences`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence {
+    /// Name of its first named track, if any
+    pub name: String,
+    pub length: Duration,
+}
+
+/// A MIDI file laid out on a single timeline.
+///
+/// Unlike nodi's `Sheet`, every event keeps its time and track, so playback
+/// can start anywhere.
+pub struct Song {
+    timing: Timing,
+    /// Every event, ordered by time
+    pub events: Vec<SongEvent>,
+    /// Start of every bar
+    pub bars: Vec<Duration>,
+    pub beats: Vec<Beat>,
+    /// Named places, ordered by time
+    pub markers: Vec<(String, Duration)>,
+    /// Where the last track ends
+    end_tick: u64,
+    end: Duration,
+}
+
+impl Song {
+    /// Every sequence of the file, one after the other.
+    pub fn new(smf: &Smf) -> Self {
+        Self::lay_out(smf, &sequence_tracks(smf))
+    }
+
+    /// Only `sequences` of the file, counting from 1, in that order.
+    pub fn with_sequences(smf: &Smf, sequences: &[usize]) -> Result<Self> {
+        let all = sequence_tracks(smf);
+        let chosen = sequences
+            .iter()
+            .map(|&sequence| {
+                all.get(sequence.wrapping_sub(1))
+                    .cloned()
+                    .ok_or(MidiError::Sequence {
+                        sequence,
+                        count: all.len(),
+                    })
+            })
+            .collect::<Result<Vec<Vec<usize>>>>()?;
+        Ok(Self::lay_out(smf, &chosen))
+    }
+
+    /// The sequences of the file, each as if played alone.
+    pub fn sequences(smf: &Smf) -> Vec<Sequence> {
+        sequence_tracks(smf)
+            .into_iter()
+            .map(|tracks| {
+                let song = Self::lay_out(smf, &[tracks]);
+                let name = song.events.iter().find_map(|event| match &event.kind {
+                    SongEventKind::Meta(Meta::TrackName { name, .. }) if !name.is_empty() => {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                });
+                Sequence {
+                    name: name.unwrap_or_default(),
+                    length: song.length(),
+                }
+            })
+            .collect()
+    }
+
+    /// Play the tracks of each of `sequences` together, and the sequences
+    /// one after the other.
+    fn lay_out(smf: &Smf, sequences: &[Vec<usize>]) -> Self {
+        let mut events = Vec::new();
+        let mut offset = 0;
+        sequences.iter().enumerate().for_each(|(index, tracks)| {
+            // Sequences do not share their tempo.
+            if index > 0 {
+                events.push(SongEvent {
+                    tick: offset,
+                    track: tracks.first().copied().unwrap_or(0),
+                    time: Duration::ZERO,
+                    kind: SongEventKind::Meta(Meta::Tempo(DEFAULT_TEMPO)),
+                });
+            }
+            let mut end = offset;
+            tracks.iter().for_each(|&track| {
+                let mut tick = offset;
+                smf.tracks[track].iter().for_each(|track_event| {
+                    tick += u32::from(track_event.delta) as u64;
+                    if let Some(kind) = SongEventKind::new(track, track_event.kind) {
+                        events.push(SongEvent {
+                            tick,
+                            track,
+                            time: Duration::ZERO,
+                            kind,
+                        });
+                    }
+                });
+                end = end.max(tick);
+            });
+            offset = end;
+        });
+        let end_tick = offset;
+        // Stable, so simultaneous events keep their track order.
+        events.sort_by_key(|event| event.tick);
+
+        let mut song = Self {
+            timing: smf.header.timing,
+            events,
+            bars: Vec::new(),
+            beats: Vec::new(),
+            markers: Vec::new(),
+            end_tick,
+            end: Duration::ZERO,
+        };
+        let ticks: Vec<u64> = song.events.iter().map(|event| event.tick).collect();
+        song.times_at(&ticks)
+            .into_iter()
+            .zip(song.events.iter_mut())
+            .for_each(|(time, event)| event.time = time);
+        let beat_ticks = song.beat_ticks();
+        let ticks: Vec<u64> = beat_ticks.iter().map(|&(tick, _)| tick).collect();
+        let mut bar = 0;
+        song.beats = song
+            .times_at(&ticks)
+            .into_iter()
+            .zip(beat_ticks)
+            .map(|(time, (_, starts_bar))| {
+                bar += starts_bar as usize;
+                Beat {
+                    time,
+                    bar: starts_bar.then_some(bar),
+                }
+            })
+            .collect();
+        song.bars = song
+            .beats
+            .iter()
+            .filter(|beat| beat.bar.is_some())
+            .map(|beat| beat.time)
+            .collect();
+        song.markers = song
+            .events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                SongEventKind::Meta(Meta::Marker(name)) => Some((name.clone(), event.time)),
+                _ => None,
+            })
+            .collect();
+        song.end = song.times_at(&[end_tick])[0];
+        song
+    }
+
+    /// Song time of the end of the last track.
+    pub fn length(&self) -> Duration {
+        self.end
+    }
+
+    /// Everything known ahead: the notes, paired up from note ons and note
+    /// offs, the beats and the lyrics. Notes never released last until the
+    /// end of the song.
+    pub fn score(&self) -> Arc<Score> {
+        let mut notes = Vec::new();
+        let mut sounding = BTreeMap::new();
+        self.events.iter().for_each(|event| {
+            let (channel, message) = match event.kind {
+                SongEventKind::Midi(midi) => (u8::from(midi.channel), midi.message),
+                _ => return,
+            };
+            match message {
+                MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                    // Retriggered notes end where they start again.
+                    let started = (event.time, event.track);
+                    if let Some((begin, track)) = sounding.insert((channel, u8::from(key)), started)
+                    {
+                        notes.push(SongNote {
+                            begin,
+                            end: event.time,
+                            track,
+                            channel,
+                            key: u8::from(key),
+                        });
+                    }
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    if let Some((begin, track)) = sounding.remove(&(channel, u8::from(key))) {
+                        notes.push(SongNote {
+                            begin,
+                            end: event.time,
+                            track,
+                            channel,
+                            key: u8::from(key),
+                        });
+                    }
+                }
+                _ => (),
+            }
+        });
+        notes.extend(
+            sounding
+                .into_iter()
+                .map(|((channel, key), (begin, track))| SongNote {
+                    begin,
+                    end: self.end,
+                    track,
+                    channel,
+                    key,
+                }),
+        );
+        notes.sort_by_key(|note| note.begin);
+        Arc::new(Score {
+            notes,
+            beats: self.beats.clone(),
+            lyrics: self.lyrics(),
+        })
+    }
+
+    /// Lyric events split into lines, or if there are none, the text events
+    /// of a karaoke file.
+    ///
+    /// Lyric events break lines with carriage returns or line feeds, and
+    /// karaoke files start lines with `/` and paragraphs with `\\`.
+    fn lyrics(&self) -> Vec<Syllable> {
+        let texts = |lyric: bool| {
+            self.events
+                .iter()
+                .filter_map(move |event| match &event.kind {
+                    SongEventKind::Meta(Meta::Lyric(text)) if lyric => Some((event.time, text)),
+                    SongEventKind::Meta(Meta::Text(text)) if !lyric => Some((event.time, text)),
+                    _ => None,
+                })
+        };
+        let mut syllables: Vec<(Duration, &String)> = texts(true).collect();
+        // Karaoke files have `@` headers, telling their title and such.
+        if syllables.is_empty() && texts(false).any(|(_, text)| text.starts_with('@')) {
+            syllables = texts(false)
+                .filter(|(_, text)| !text.starts_with('@'))
+                .collect();
+        }
+
+        let breaks = ['\r', '\n'];
+        let mut line = true;
+        syllables
+            .into_iter()
+            .filter_map(|(time, text)| {
+                let text = match text.strip_prefix(['/', '\\']) {
+                    Some(text) => {
+                        line = true;
+                        text
+                    }
+                    None => text,
+                };
+                line |= text.starts_with(breaks);
+                let ends_line = text.ends_with(breaks);
+                let text = text.trim_matches(breaks);
+                if text.is_empty() {
+                    line |= ends_line;
+                    return None;
+                }
+                let syllable = Syllable {
+                    time,
+                    text: text.to_owned(),
+                    line,
+                };
+                line = ends_line;
+                Some(syllable)
+            })
+            .collect()
+    }
+
+    /// Index of the first event at or after `position`.
+    pub fn index_at(&self, position: Duration) -> usize {
+        self.events.partition_point(|event| event.time < position)
+    }
+
+    /// Find `spec` in the song: a time as `[MINUTES:]SECONDS`, a bar as
+    /// `bar:NUMBER` counting from 1, or a marker as `marker:NAME`.
+    ///
+    /// An `end` bar reaches up to the start of the next one.
+    pub fn position(&self, spec: &str, end: bool) -> Result<Duration> {
+        let error = |reason: &str| MidiError::Position {
+            spec: spec.to_owned(),
+            reason: reason.to_owned(),
+        };
+        if let Some(bar) = spec.strip_prefix("bar:") {
+            let bar: usize = bar.trim().parse().map_err(|_| error("not a bar number"))?;
+            if bar == 0 || bar > self.bars.len() {
+                return Err(error(&format!("there are bars 1 to {}", self.bars.len())));
+            }
+            let index = if end { bar } else { bar - 1 };
+            Ok(self.bars.get(index).copied().unwrap_or(self.end))
+        } else if let Some(name) = spec.strip_prefix("marker:") {
+            self.markers
+                .iter()
+                .find(|(marker, _)| marker.trim() == name.trim())
+                .map(|&(_, time)| time)
+                .ok_or_else(|| error("no such marker"))
+        } else {
+            let (minutes, seconds) = spec.split_once(':').unwrap_or(("0", spec));
+            match (minutes.trim().parse::<u32>(), seconds.trim().parse::<f64>()) {
+                (Ok(minutes), Ok(seconds)) if seconds >= 0.0 => {
+                    Ok(Duration::minutes(minutes as i64) + Duration::seconds_f64(seconds))
+                }
+                _ => Err(error("not a time, bar:NUMBER or marker:NAME")),
+            }
+        }
+    }
+
+    fn ticks_per_beat(&self) -> u64 {
+        match self.timing {
+            Timing::Metrical(n) => u16::from(n) as u64,
+            // Timecode has no beats; count as if at the default tempo.
+            Timing::Timecode(fps, subframes) => {
+                (fps.as_f32() * subframes as f32 * DEFAULT_TEMPO as f32 / 1_000_000.0) as u64
+            }
+        }
+    }
+
+    fn timer(&self) -> Box<dyn Timer> {
+        match self.timing {
+            Timing::Metrical(n) => Box::new(Ticker::with_initial_tempo(n.into(), DEFAULT_TEMPO)),
+            Timing::Timecode(fps, subframes) => Box::new(TimecodeTicker::new(fps, subframes)),
+        }
+    }
+
+    /// Convert ascending `ticks` to song time, following tempo changes.
+    fn times_at(&self, ticks: &[u64]) -> Vec<Duration> {
+        let mut timer = self.timer();
+        let mut tempos = self
+            .events
+            .iter()
+            .filter_map(|event| match event.kind {
+                SongEventKind::Meta(Meta::Tempo(tempo)) => Some((event.tick, tempo)),
+                _ => None,
+            })
+            .peekable();
+        let mut last_tick = 0;
+        let mut time = Duration::ZERO;
+        let mut advance = |timer: &mut Box<dyn Timer>, tick: u64| {
+            time += Duration::try_from(timer.sleep_duration((tick - last_tick) as u32)).unwrap();
+            last_tick = tick;
+            time
+        };
+
+        ticks
+            .iter()
+            .map(|&tick| {
+                while let Some(&(tempo_tick, tempo)) = tempos.peek() {
+                    if tempo_tick > tick {
+                        break;
+                    }
+                    advance(&mut timer, tempo_tick);
+                    timer.change_tempo(tempo);
+                    tempos.next();
+                }
+                advance(&mut timer, tick)
+            })
+            .collect()
+    }
+
+    /// Tick of every beat, and whether it starts a bar, following time
+    /// signature changes.
+    fn beat_ticks(&self) -> Vec<(u64, bool)> {
+        let ticks_per_beat = self.ticks_per_beat();
+        let mut signatures = self
+            .events
+            .iter()
+            .filter_map(|event| match event.kind {
+                SongEventKind::Meta(Meta::TimeSignature(numerator, denominator)) => {
+                    Some((event.tick, numerator, denominator))
+                }
+                _ => None,
+            })
+            .peekable();
+        // 4/4 unless the file says otherwise.
+        let mut beats_per_bar = 4;
+        let mut ticks_per_note = ticks_per_beat;
+        let mut beats = Vec::new();
+        let mut tick = 0;
+        while tick < self.end_tick {
+            while let Some(&(signature_tick, numerator, denominator)) = signatures.peek() {
+                if signature_tick > tick {
+                    break;
+                }
+                beats_per_bar = numerator.max(1);
+                ticks_per_note = 2u64
+                    .checked_pow(denominator as u32)
+                    .map_or(1, |note| (ticks_per_beat * 4 / note).max(1));
+                signatures.next();
+            }
+            for beat in 0..beats_per_bar {
+                if tick >= self.end_tick {
+                    break;
+                }
+                beats.push((tick, beat == 0));
+                tick += ticks_per_note;
+            }
+        }
+        beats
+    }
+}
+
+/// Tracks of each sequence of the file: every track of a Format 2 file is
+/// a sequence of its own, other files are a single sequence.
+fn sequence_tracks(smf: &Smf) -> Vec<Vec<usize>> {
+    match smf.header.format {
+        Format::Parallel => vec![(0..smf.tracks.len()).collect()],
+        Format::SingleTrack | Format::Sequential => {
+            (0..smf.tracks.len()).map(|track| vec![track]).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nodi::midly::{
+        Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    };
+    use time::Duration;
+
+    use super::{Meta, Sequence, Song, SongEventKind, Syllable};
+
+    fn event(delta: u32, kind: TrackEventKind<'static>) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn song_times_and_bars() {
+        let note_on = TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: 60.into(),
+                vel: 100.into(),
+            },
+        };
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(480.into())));
+        smf.tracks.push(vec![
+            event(
+                0,
+                TrackEventKind::Meta(MetaMessage::TimeSignature(3, 2, 24, 8)),
+            ),
+            event(960, TrackEventKind::Meta(MetaMessage::Marker(b"Chorus"))),
+            // Twice as fast from the second bar on.
+            event(
+                480,
+                TrackEventKind::Meta(MetaMessage::Tempo(250_000.into())),
+            ),
+            event(1500, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        smf.tracks
+            .push(vec![event(960, note_on), event(960, note_on)]);
+        let song = Song::new(&smf);
+
+        let times: Vec<Duration> = song.events.iter().map(|event| event.time).collect();
+        assert_eq!(
+            times,
+            vec![
+                Duration::ZERO,
+                Duration::seconds(1),
+                Duration::seconds(1),
+                Duration::milliseconds(1500),
+                Duration::milliseconds(1750),
+            ]
+        );
+        assert!(matches!(
+            &song.events[1].kind,
+            SongEventKind::Meta(Meta::Marker(name)) if name == "Chorus"
+        ));
+        assert_eq!(
+            song.bars,
+            vec![
+                Duration::ZERO,
+                Duration::milliseconds(1500),
+                Duration::milliseconds(2250)
+            ]
+        );
+        assert_eq!(song.length(), Duration::microseconds(2_281_250));
+        // Three quarter notes a bar, twice as fast from the second bar on.
+        let beats: Vec<(Duration, Option<usize>)> = song
+            .beats
+            .iter()
+            .map(|beat| (beat.time, beat.bar))
+            .collect();
+        assert_eq!(
+            beats,
+            vec![
+                (Duration::ZERO, Some(1)),
+                (Duration::milliseconds(500), None),
+                (Duration::seconds(1), None),
+                (Duration::milliseconds(1500), Some(2)),
+                (Duration::milliseconds(1750), None),
+                (Duration::milliseconds(2000), None),
+                (Duration::milliseconds(2250), Some(3)),
+            ]
+        );
+        let score: Vec<(Duration, Duration)> = song
+            .score()
+            .notes
+            .iter()
+            .map(|note| (note.begin, note.end))
+            .collect();
+        assert_eq!(
+            score,
+            vec![
+                (Duration::seconds(1), Duration::milliseconds(1750)),
+                (Duration::milliseconds(1750), song.length()),
+            ]
+        );
+        assert_eq!(song.index_at(Duration::seconds(1)), 1);
+        assert_eq!(song.index_at(Duration::milliseconds(1001)), 3);
+
+        let position = |spec| song.position(spec, false).ok();
+        assert_eq!(position("1:02.5"), Some(Duration::milliseconds(62_500)));
+        assert_eq!(position("bar:2"), Some(Duration::milliseconds(1500)));
+        assert_eq!(
+            song.position("bar:2", true).ok(),
+            Some(Duration::milliseconds(2250))
+        );
+        assert_eq!(song.position("bar:3", true).ok(), Some(song.length()));
+        assert_eq!(position("marker:Chorus"), Some(Duration::seconds(1)));
+        assert!(position("bar:4").is_none());
+        assert!(position("marker:Verse").is_none());
+        assert!(position("soon").is_none());
+    }
+
+    #[test]
+    fn song_bad_time_signature() {
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(480.into())));
+        smf.tracks.push(vec![
+            // A 2^255th note a beat: ignored, so still 4/4.
+            event(
+                0,
+                TrackEventKind::Meta(MetaMessage::TimeSignature(3, 255, 24, 8)),
+            ),
+            event(960, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        let song = Song::new(&smf);
+        assert!(song.events.is_empty());
+        assert_eq!(song.beats.len(), 2);
+
+        assert_eq!(Meta::TimeSignature(6, 3).to_string(), "6/8");
+        assert_eq!(Meta::TimeSignature(3, 255).to_string(), "3/2^255");
+    }
+
+    #[test]
+    fn song_sequences() {
+        let note_on = |key: u8| {
+            event(
+                0,
+                TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: MidiMessage::NoteOn {
+                        key: key.into(),
+                        vel: 100.into(),
+                    },
+                },
+            )
+        };
+        let mut smf = Smf::new(Header::new(
+            Format::Sequential,
+            Timing::Metrical(480.into()),
+        ));
+        // A fast intro of a beat, then a verse of two beats.
+        smf.tracks.push(vec![
+            event(0, TrackEventKind::Meta(MetaMessage::TrackName(b"Intro"))),
+            event(0, TrackEventKind::Meta(MetaMessage::Tempo(250_000.into()))),
+            note_on(60),
+            event(480, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        smf.tracks.push(vec![
+            event(0, TrackEventKind::Meta(MetaMessage::TrackName(b"Verse"))),
+            note_on(64),
+            event(960, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+
+        let sequence = |name: &str, millis| Sequence {
+            name: name.to_owned(),
+            length: Duration::milliseconds(millis),
+        };
+        assert_eq!(
+            Song::sequences(&smf),
+            vec![sequence("Intro", 250), sequence("Verse", 1000)]
+        );
+        // Every sequence once, the verse back at the default tempo.
+        assert_eq!(Song::new(&smf).length(), Duration::milliseconds(1250));
+
+        let song = Song::with_sequences(&smf, &[2, 1, 1]).unwrap();
+        let notes: Vec<(Duration, u8)> = song
+            .score()
+            .notes
+            .iter()
+            .map(|note| (note.begin, note.key))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                (Duration::ZERO, 64),
+                (Duration::milliseconds(1000), 60),
+                (Duration::milliseconds(1250), 60),
+            ]
+        );
+        assert_eq!(song.length(), Duration::milliseconds(1500));
+        assert!(Song::with_sequences(&smf, &[3]).is_err());
+        assert!(Song::with_sequences(&smf, &[0]).is_err());
+
+        // Tracks of other files play together, as a single sequence.
+        smf.header.format = Format::Parallel;
+        assert_eq!(Song::sequences(&smf), vec![sequence("Intro", 500)]);
+    }
+
+    #[test]
+    fn song_lyrics() {
+        let text = |delta, text| event(delta, TrackEventKind::Meta(MetaMessage::Text(text)));
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(480.into())));
+        // A karaoke file, a syllable every half second.
+        smf.tracks.push(vec![
+            text(0, b"@KMIDI KARAOKE FILE"),
+            text(0, b"@TTwinkle"),
+            text(480, b"\\Twin"),
+            text(480, b"kle "),
+            text(480, b"twin"),
+            text(480, b"kle"),
+            text(480, b"/Lit"),
+            text(480, b"tle "),
+            text(480, b"star"),
+        ]);
+        let score = Song::new(&smf).score();
+        assert_eq!(score.lyrics.len(), 7);
+        assert_eq!(
+            score.lyrics[4],
+            Syllable {
+                time: Duration::milliseconds(2500),
+                text: "Lit".to_owned(),
+                line: true,
+            }
+        );
+        let line = |ms| {
+            score
+                .lyric_line(Duration::milliseconds(ms))
+                .map(|(line, reached)| {
+                    let line: Vec<&str> = line.iter().map(|s| s.text.as_str()).collect();
+                    (line.concat(), reached)
+                })
+        };
+        assert_eq!(line(0), Some(("Twinkle twinkle".to_owned(), 0)));
+        assert_eq!(line(1200), Some(("Twinkle twinkle".to_owned(), 2)));
+        assert_eq!(line(2500), Some(("Little star".to_owned(), 1)));
+        assert_eq!(line(9000), Some(("Little star".to_owned(), 3)));
+
+        // Lyric events come first, and break lines at line ends.
+        let lyric = |delta, text| event(delta, TrackEventKind::Meta(MetaMessage::Lyric(text)));
+        smf.tracks[0].extend([lyric(480, b"Up "), lyric(480, b"a\r"), lyric(480, b"bove")]);
+        let score = Song::new(&smf).score();
+        let lyrics: Vec<(&str, bool)> = score
+            .lyrics
+            .iter()
+            .map(|syllable| (syllable.text.as_str(), syllable.line))
+            .collect();
+        assert_eq!(lyrics, vec![("Up ", true), ("a", false), ("bove", true)]);
+
+        // Text alone is no lyrics.
+        smf.tracks[0] = vec![text(0, b"Copyright")];
+        assert!(Song::new(&smf).score().lyric_line(Duration::ZERO).is_none());
+    }
+}
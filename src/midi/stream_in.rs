@@ -0,0 +1,264 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use std::{
+    fs::File,
+    io::{self, Read},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{bounded, select, unbounded, Receiver, Sender};
+
+use super::clock::Clock;
+use super::error::{MidiError, Result};
+use super::midi_in::tap;
+use super::mixer::Mixer;
+use super::playback::Transport;
+use super::recorder::Recorder;
+use super::song::Score;
+use super::stream_parser::StreamParser;
+use super::thru::Thru;
+use crate::{midi::MidiProvider, options::Options, MidiData, Notice};
+
+/// How many bytes are read at once; a read returns as soon as any arrive.
+const CHUNK: usize = 256;
+
+/// MIDI from a raw byte stream: stdin, a named pipe or a device file such as
+/// a serial port, set up beforehand (e.g. its baud rate with `stty`).
+///
+/// Everything comes from one source, 0. The stream ending disconnects the
+/// receivers, as a MIDI file ending does.
+pub struct StreamIn {
+    midi_recv: Receiver<MidiData>,
+    notice_recv: Receiver<Notice>,
+    clock: Clock,
+    /// Dropped to stop parsing; the thread reading may stay blocked in a
+    /// read, but it no longer holds anything
+    stop_send: Option<Sender<()>>,
+    parser: Option<JoinHandle<()>>,
+    recorder: Recorder,
+    /// Mutes and solos channels
+    mixer: Mixer,
+    /// Pass the messages on to `midi_recv`; see [`tap`]
+    taps: Vec<JoinHandle<()>>,
+}
+
+impl MidiProvider for StreamIn {
+    fn get_midi_in_recv(&self) -> Receiver<MidiData> {
+        self.midi_recv.clone()
+    }
+
+    fn get_notice_recv(&self) -> Receiver<Notice> {
+        self.notice_recv.clone()
+    }
+
+    fn get_clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    fn get_transport(&self) -> Option<Transport> {
+        None
+    }
+
+    fn get_recorder(&self) -> Option<Recorder> {
+        Some(self.recorder.clone())
+    }
+
+    fn get_mixer(&self) -> Option<Mixer> {
+        Some(self.mixer.clone())
+    }
+
+    fn get_score(&self) -> Option<Arc<Score>> {
+        None
+    }
+
+    /// Opening a named pipe waits for something to write to it.
+    fn new(opts: &Options) -> Result<Self> {
+        let path = opts.stream.as_deref().unwrap_or("-");
+        let stream: Box<dyn Read + Send> = match path {
+            "-" => Box::new(io::stdin()),
+            path => Box::new(File::open(path).map_err(|source| match source.kind() {
+                io::ErrorKind::NotFound => MidiError::FileNotFound {
+                    path: path.to_owned(),
+                },
+                _ => MidiError::Io {
+                    path: path.to_owned(),
+                    source,
+                },
+            })?),
+        };
+        let name = match path {
+            "-" => "stdin",
+            path => path,
+        };
+        Self::with_stream(opts, name, stream)
+    }
+}
+
+impl StreamIn {
+    fn with_stream(opts: &Options, name: &str, mut stream: Box<dyn Read + Send>) -> Result<Self> {
+        let (midi_send, midi_recv) = unbounded();
+        let (notice_send, notice_recv) = unbounded();
+        let clock = Clock::new();
+        let mixer = Mixer::new();
+        let (recorder, midi_recv, taps) = tap(opts, &clock, &notice_send, &mixer, midi_recv)?;
+        let thru = Thru::from_options(opts)?.map(|thru| thru.with_mixer(mixer.clone()));
+
+        let (bytes_send, bytes_recv) = unbounded::<Vec<u8>>();
+        thread::spawn(move || {
+            let mut buffer = [0; CHUNK];
+            while let Ok(read @ 1..) = stream.read(&mut buffer) {
+                if bytes_send.send(buffer[..read].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let _send = notice_send.send(Notice::Connected {
+            source: 0,
+            port: name.to_owned(),
+        });
+        let (stop_send, stop_recv) = bounded::<()>(0);
+        let parse_clock = clock.clone();
+        let parser = thread::spawn(move || {
+            let mut parser = StreamParser::new();
+            loop {
+                let bytes = select! {
+                    recv(stop_recv) -> _ => break,
+                    recv(bytes_recv) -> bytes => match bytes {
+                        Ok(bytes) => bytes,
+                        Err(_) => break,
+                    },
+                };
+                let timestamp = parse_clock.now();
+                for message in bytes.into_iter().filter_map(|byte| parser.push(byte)) {
+                    if let Some(thru) = &thru {
+                        thru.send(&message);
+                    }
+                    let _send = midi_send.send(MidiData {
+                        message,
+                        timestamp,
+                        source: 0,
+                    });
+                }
+            }
+        });
+
+        Ok(StreamIn {
+            midi_recv,
+            notice_recv,
+            clock,
+            stop_send: Some(stop_send),
+            parser: Some(parser),
+            recorder,
+            mixer,
+            taps,
+        })
+    }
+
+    /// Stop reading, and write what is being recorded or logged.
+    pub fn close(&mut self) {
+        self.stop_send.take();
+        if let Some(parser) = self.parser.take() {
+            let _ = parser.join();
+        }
+        self.taps.drain(..).for_each(|tap| {
+            let _ = tap.join();
+        });
+    }
+}
+
+impl Drop for StreamIn {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use clap::Parser;
+    use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+
+    use super::StreamIn;
+    use crate::midi::test_util::TempPath;
+    use crate::midi::{MidiError, MidiProvider};
+    use crate::{Notice, Options};
+
+    /// A stream that blocks until bytes are sent, and ends when dropped.
+    struct Pipe(Receiver<Vec<u8>>);
+
+    impl io::Read for Pipe {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let bytes = self.0.recv().unwrap_or_default();
+            buffer[..bytes.len()].copy_from_slice(&bytes);
+            Ok(bytes.len())
+        }
+    }
+
+    #[test]
+    fn stream_in_reads() {
+        let path = TempPath::new(".bin");
+        std::fs::write(&path.0, [0x90, 60, 100, 64, 100, 0xF0, 0x7E, 0xF7, 0xF8]).unwrap();
+        let opts = Options::parse_from(["mirmidivi-rs", "--stream", path.to_str()]);
+        let midi = StreamIn::new(&opts).unwrap();
+        std::fs::remove_file(&path.0).unwrap();
+
+        let messages: Vec<_> = midi
+            .get_midi_in_recv()
+            .iter()
+            .map(|midi| midi.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                vec![0x90, 60, 100],
+                vec![0x90, 64, 100],
+                vec![0xF0, 0x7E, 0xF7],
+                vec![0xF8],
+            ]
+        );
+        assert!(matches!(
+            midi.get_notice_recv().try_recv(),
+            Ok(Notice::Connected { source: 0, .. })
+        ));
+
+        assert!(matches!(
+            StreamIn::new(&opts),
+            Err(MidiError::FileNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn stream_in_stdin_with_curses() {
+        let opts = |args: &[&str]| Options::parse_from(["mirmidivi-rs"].iter().chain(args));
+        // Both would read stdin.
+        assert!(opts(&["--stream", "-", "-r", "curses"]).check().is_err());
+        assert!(opts(&["--stream", "-"]).check().is_ok());
+        assert!(opts(&["--stream", "/dev/ttyUSB0", "-r", "curses"])
+            .check()
+            .is_ok());
+    }
+
+    #[test]
+    fn stream_in_close() {
+        let (bytes_send, bytes_recv) = unbounded();
+        let opts = Options::parse_from(["mirmidivi-rs"]);
+        let mut midi = StreamIn::with_stream(&opts, "pipe", Box::new(Pipe(bytes_recv))).unwrap();
+        let midi_recv = midi.get_midi_in_recv();
+
+        // A message split across reads.
+        bytes_send.send(vec![0xB0, 7]).unwrap();
+        bytes_send.send(vec![100]).unwrap();
+        let data = midi_recv.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(data.message, vec![0xB0, 7, 100]);
+        assert_eq!(data.source, 0);
+
+        // Closes while the stream is still open.
+        midi.close();
+        assert!(matches!(
+            midi_recv.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::Message;
+
+/// Splits a raw MIDI byte stream, as sent down a MIDI cable, into messages.
+///
+/// Follows running status, so data bytes without a status byte reuse the
+/// last channel status. Real-time messages may come anywhere, even in the
+/// middle of another message. A system exclusive message runs from `F0` to
+/// `F7`; one cut short by another status byte is dropped. Data bytes with
+/// no status to go with are dropped as well.
+#[derive(Default)]
+pub struct StreamParser {
+    /// Channel status to reuse for data bytes without one
+    running: Option<u8>,
+    /// The message so far, status byte first
+    message: Message,
+    /// Inside a system exclusive message
+    sysex: bool,
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in the next byte, and return the message it completes, if any.
+    pub fn push(&mut self, byte: u8) -> Option<Message> {
+        match byte {
+            0xF8..=0xFF => Some(vec![byte]),
+            0xF0 => {
+                self.running = None;
+                self.sysex = true;
+                self.message = vec![byte];
+                None
+            }
+            0xF7 if self.sysex => {
+                self.sysex = false;
+                self.message.push(byte);
+                Some(std::mem::take(&mut self.message))
+            }
+            0xF7 => None,
+            0x80..=0xF6 => {
+                self.sysex = false;
+                // System common messages cancel running status.
+                self.running = (byte < 0xF0).then_some(byte);
+                self.message = vec![byte];
+                self.complete()
+            }
+            _ if self.sysex => {
+                self.message.push(byte);
+                None
+            }
+            _ => {
+                if self.message.is_empty() {
+                    self.message.push(self.running?);
+                }
+                self.message.push(byte);
+                self.complete()
+            }
+        }
+    }
+
+    /// The message, if it has all the bytes its status calls for.
+    fn complete(&mut self) -> Option<Message> {
+        (self.message.len() == length(self.message[0])).then(|| std::mem::take(&mut self.message))
+    }
+}
+
+/// How many bytes a message starting with `status` has, system exclusive
+/// messages aside.
+pub fn length(status: u8) -> usize {
+    match status {
+        0xC0..=0xDF | 0xF1 | 0xF3 => 2,
+        0x80..=0xEF | 0xF2 => 3,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamParser;
+
+    fn parse(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut parser = StreamParser::new();
+        bytes.iter().filter_map(|byte| parser.push(*byte)).collect()
+    }
+
+    #[test]
+    fn stream_parser_running_status() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 64, 100, 60, 0, 0xC1, 5, 6, 0xE0, 0, 0x40]),
+            vec![
+                vec![0x90, 60, 100],
+                vec![0x90, 64, 100],
+                vec![0x90, 60, 0],
+                vec![0xC1, 5],
+                vec![0xC1, 6],
+                vec![0xE0, 0, 0x40],
+            ]
+        );
+        // Data with no status to go with, and after system common messages.
+        assert_eq!(
+            parse(&[1, 2, 0x80, 60, 0, 0xF3, 1, 2, 3, 0xF2, 0, 8, 0xF6, 4]),
+            vec![
+                vec![0x80, 60, 0],
+                vec![0xF3, 1],
+                vec![0xF2, 0, 8],
+                vec![0xF6],
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_parser_sysex_and_real_time() {
+        assert_eq!(
+            parse(&[0x90, 60, 0xF8, 100, 0xF0, 0x7E, 0xFE, 0x7F, 0xF7, 62, 100]),
+            vec![
+                vec![0xF8],
+                vec![0x90, 60, 100],
+                vec![0xFE],
+                vec![0xF0, 0x7E, 0x7F, 0xF7],
+                // Running status does not outlive system exclusive.
+            ]
+        );
+        // Cut short, then a stray end.
+        assert_eq!(
+            parse(&[0xF0, 0x43, 0x10, 0xB0, 7, 100, 0xF7, 10, 0]),
+            vec![vec![0xB0, 7, 100], vec![0xB0, 10, 0]]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Helpers shared by the tests of the MIDI providers.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use clap::Parser;
+use nodi::midly::Smf;
+
+use super::{MidiPlayer, MidiProvider};
+use crate::options::Options;
+
+/// A file or directory for a test, under a name of its own, removed
+/// when dropped.
+pub struct TempPath(pub PathBuf);
+
+impl TempPath {
+    pub fn new(extension: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "mirmidivi-rs-{}-{}{}",
+            std::process::id(),
+            COUNT.fetch_add(1, SeqCst),
+            extension
+        );
+        Self(std::env::temp_dir().join(name))
+    }
+
+    pub fn with_smf(smf: &Smf) -> Self {
+        let path = Self::new(".mid");
+        smf.save(&path.0).unwrap();
+        path
+    }
+
+    pub fn with_bytes(bytes: &[u8]) -> Self {
+        let path = Self::new(".mid");
+        std::fs::write(&path.0, bytes).unwrap();
+        path
+    }
+
+    pub fn dir() -> Self {
+        let path = Self::new("");
+        std::fs::create_dir_all(&path.0).unwrap();
+        path
+    }
+
+    pub fn to_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    /// Options to play this, then `args`.
+    pub fn opts(&self, args: &[&str]) -> Options {
+        Options::parse_from(
+            ["mirmidivi-rs", "--midifile", self.to_str()]
+                .iter()
+                .chain(args),
+        )
+    }
+
+    pub fn play(&self, args: &[&str]) -> MidiPlayer {
+        MidiPlayer::new(&self.opts(args)).unwrap()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = match self.0.is_dir() {
+            true => std::fs::remove_dir_all(&self.0),
+            false => std::fs::remove_file(&self.0),
+        };
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{unbounded, Sender};
+use midir::MidiOutputConnection;
+use nodi::{Connection, MidiEvent};
+
+use super::error::Result;
+use super::mixer::Mixer;
+use super::ports::connect_output;
+use crate::{options::Options, Message};
+
+/// Somewhere MIDI messages can be sent to.
+pub trait OutputConnection: Send {
+    fn send(&mut self, message: &[u8]);
+}
+
+impl OutputConnection for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) {
+        let _ = MidiOutputConnection::send(self, message);
+    }
+}
+
+/// Forwards MIDI messages to an output port, optionally delayed.
+///
+/// The output is closed once every clone has been dropped, after the
+/// messages still waiting for their delay have been sent.
+#[derive(Clone)]
+pub struct Thru {
+    shared: Arc<Shared>,
+    /// Holds back the live messages it does not let through
+    mixer: Option<Mixer>,
+}
+
+struct Shared {
+    thru_send: Option<Sender<(Instant, Message)>>,
+    handler: Option<JoinHandle<()>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.thru_send.take();
+        if let Some(handler) = self.handler.take() {
+            let _ = handler.join();
+        }
+    }
+}
+
+impl Thru {
+    pub fn new(mut output: Box<dyn OutputConnection>, delay: Duration) -> Self {
+        let (thru_send, thru_recv) = unbounded::<(Instant, Message)>();
+        let handler = thread::spawn(move || {
+            thru_recv.iter().for_each(|(arrival, message)| {
+                let due = arrival + delay;
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+                output.send(&message);
+            })
+        });
+        Self {
+            shared: Arc::new(Shared {
+                thru_send: Some(thru_send),
+                handler: Some(handler),
+            }),
+            mixer: None,
+        }
+    }
+
+    /// Leave out live messages that `mixer` does not let through.
+    pub fn with_mixer(mut self, mixer: Mixer) -> Self {
+        self.mixer = Some(mixer);
+        self
+    }
+
+    /// Open the thru port requested in `opts`, if any.
+    pub fn from_options(opts: &Options) -> Result<Option<Self>> {
+        match &opts.thru {
+            Some(spec) => Ok(Some(Self::new(
+                Box::new(connect_output(spec)?),
+                Duration::from_millis(opts.thru_delay),
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn send(&self, message: &[u8]) {
+        if let Some(mixer) = &self.mixer {
+            if !mixer.lets_through(message) {
+                return;
+            }
+        }
+        if let Some(thru_send) = &self.shared.thru_send {
+            let _send = thru_send.send((Instant::now(), message.to_vec()));
+        }
+    }
+}
+
+impl Connection for Thru {
+    fn play(&mut self, event: MidiEvent) -> bool {
+        let mut message = Vec::with_capacity(8);
+        event.write(&mut message).unwrap();
+        self.send(&message);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use super::{OutputConnection, Thru};
+
+    type Sent = Arc<Mutex<Vec<(Instant, Vec<u8>)>>>;
+
+    struct MockOutput(Sent);
+
+    impl OutputConnection for MockOutput {
+        fn send(&mut self, message: &[u8]) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((Instant::now(), message.to_vec()));
+        }
+    }
+
+    #[test]
+    fn thru_delays_messages() {
+        let sent = Sent::default();
+        let thru = Thru::new(
+            Box::new(MockOutput(sent.clone())),
+            Duration::from_millis(30),
+        );
+
+        let begin = Instant::now();
+        thru.send(&[0x90, 0x3C, 0x64]);
+        thru.send(&[0x80, 0x3C, 0x00]);
+        // Dropping waits for the delayed messages.
+        drop(thru);
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].1, vec![0x90, 0x3C, 0x64]);
+        assert_eq!(sent[1].1, vec![0x80, 0x3C, 0x00]);
+        assert!(sent[0].0 - begin >= Duration::from_millis(30));
+    }
+}
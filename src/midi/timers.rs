@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use nodi::{midly::Fps, Timer};
+
+/// Timer for SMPTE timecode files, whose ticks are subdivisions of a video
+/// frame rather than of a beat.
+///
+/// Tempo changes do not apply to timecode, so they are ignored.
+#[derive(Debug)]
+pub struct TimecodeTicker {
+    micros_per_tick: f64,
+}
+
+impl TimecodeTicker {
+    pub fn new(fps: Fps, subframes: u8) -> Self {
+        let fps = match fps {
+            Fps::Fps29 => 30.0 / 1.001,
+            fps => fps.as_int() as f64,
+        };
+        Self {
+            micros_per_tick: 1_000_000.0 / (fps * subframes as f64),
+        }
+    }
+}
+
+impl Timer for TimecodeTicker {
+    fn change_tempo(&mut self, _tempo: u32) {}
+
+    fn sleep_duration(&mut self, n_ticks: u32) -> Duration {
+        let t = self.micros_per_tick * n_ticks as f64;
+        if t > 0.0 {
+            Duration::from_micros(t as u64)
+        } else {
+            Duration::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nodi::{midly::Fps, Timer};
+
+    use super::TimecodeTicker;
+
+    #[test]
+    fn timecode_ticks() {
+        let mut ticker = TimecodeTicker::new(Fps::Fps25, 40);
+        assert_eq!(ticker.sleep_duration(1000), Duration::from_secs(1));
+        ticker.change_tempo(250_000);
+        assert_eq!(ticker.sleep_duration(25), Duration::from_millis(25));
+
+        let mut ticker = TimecodeTicker::new(Fps::Fps29, 100);
+        // 29.97 frames per second, so a second of 30 frames takes a little longer.
+        let t = ticker.sleep_duration(3000).as_micros();
+        assert!((1_000_999..=1_001_000).contains(&t));
+    }
+}
@@ -1,5 +1,9 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+
+use std::ops::RangeInclusive;
+
+use crate::midi::{PPQS, SPEEDS, TEMPOS};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -7,7 +11,148 @@ pub struct Options {
     /// API for rendering midi
     #[clap(short, long, value_parser, default_value_t = String::from("text"))]
     pub renderer: String,
-    /// MIDI file for rendering
+    /// MIDI files for rendering, played one after the other. A directory
+    /// stands for the .mid, .midi and .kar files in it
+    #[clap(short, long, value_parser, num_args = 1..)]
+    pub midifile: Vec<String>,
+    /// Play the MIDI files in random order
+    #[clap(long)]
+    pub shuffle: bool,
+    /// Go back to the first MIDI file after the last one
+    #[clap(long)]
+    pub repeat: bool,
+    /// MIDI input port, by index or case-insensitive substring of its name.
+    /// Repeat to listen to several ports at once
     #[clap(short, long, value_parser)]
-    pub midifile: Option<String>,
+    pub port: Vec<String>,
+    /// Create a virtual MIDI input port with this name for other
+    /// applications to connect to (not on Windows)
+    #[clap(long, value_parser)]
+    pub virtual_port: Option<String>,
+    /// Send MIDI to this output port, by index or case-insensitive
+    /// substring of its name: the incoming MIDI, or the MIDI file played
+    #[clap(long, visible_alias = "output", value_parser)]
+    pub thru: Option<String>,
+    /// Delay MIDI sent to the output port by this many milliseconds, e.g. to match the
+    /// curses renderer, which redraws every 50ms
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub thru_delay: u64,
+    /// Loop the MIDI file from here: a time as [MINUTES:]SECONDS, a bar
+    /// as bar:NUMBER or a marker as marker:NAME
+    #[clap(long, value_parser)]
+    pub loop_start: Option<String>,
+    /// Loop the MIDI file up to here, in the same forms as --loop-start. The
+    /// bar given is looped through to its end
+    #[clap(long, value_parser)]
+    pub loop_end: Option<String>,
+    /// Play the MIDI file this many times as fast, from 0.5 to 2
+    #[clap(long, value_parser = parse_speed, default_value_t = 1.0)]
+    pub speed: f64,
+    /// Show the notes of the MIDI file this many milliseconds before they
+    /// are played, ahead of the playhead (curses renderer)
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub look_ahead: u64,
+    /// Color notes by MIDI channel, or by input port (curses renderer)
+    #[clap(long, value_parser = ["channel", "port"], default_value = "channel")]
+    pub color_by: String,
+    /// Only show the notes of this input port, counting from 0 in the order
+    /// the ports were given. Repeat to show several (curses renderer)
+    #[clap(long, value_parser)]
+    pub show_port: Vec<usize>,
+    /// Show the lyrics of the MIDI file instead of MIDI messages, the
+    /// syllable sung now highlighted (text renderer)
+    #[clap(long)]
+    pub lyrics: bool,
+    /// Play these sequences of a Format 2 MIDI file in this order, counting
+    /// from 1, e.g. 2,1,1. By default every sequence is played once
+    #[clap(long, value_delimiter = ',')]
+    pub sequence: Vec<usize>,
+    /// Record the MIDI input to this Standard MIDI File. The curses
+    /// renderer starts and stops recording with R, numbering the takes
+    #[clap(long, value_parser)]
+    pub record: Option<String>,
+    /// Ticks per quarter note of recordings, up to 32767
+    #[clap(long, value_parser = clap::value_parser!(u16).range(PPQS), default_value_t = 480)]
+    pub record_ppq: u16,
+    /// Tempo of recordings, in beats per minute, from 4 to 960
+    #[clap(long, value_parser = parse_tempo, default_value_t = 120.0)]
+    pub record_tempo: f64,
+    /// Record a track per MIDI channel, or per input port
+    #[clap(long, value_parser = ["channel", "port"], default_value = "channel")]
+    pub record_tracks: String,
+    /// Log the MIDI input to this file, every message with its timing and
+    /// input port, to be replayed exactly with --replay
+    #[clap(long, value_parser)]
+    pub log: Option<String>,
+    /// Replay an event log written with --log instead of listening to MIDI
+    /// ports
+    #[clap(long, value_parser)]
+    pub replay: Option<String>,
+    /// Read raw MIDI bytes from this file instead of MIDI ports: - for
+    /// stdin, a named pipe, or a device file such as a serial port
+    #[clap(long, value_parser)]
+    pub stream: Option<String>,
+    /// Join RTP-MIDI (AppleMIDI) network sessions instead of listening to
+    /// MIDI ports, on this UDP control port and the data port after it
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u16).range(1..65535),
+        num_args = 0..=1,
+        default_missing_value = "5004"
+    )]
+    pub rtp_midi: Option<u16>,
+    /// Listen for Open Sound Control messages on this UDP port instead of
+    /// MIDI ports, e.g. from SuperCollider or TidalCycles
+    #[clap(long, value_parser)]
+    pub osc: Option<u16>,
+    /// OSC address for note on messages, taking channel (from 0), note and
+    /// velocity, either up to 127 or a float from 0 to 1
+    #[clap(long, value_parser, default_value_t = String::from("/noteon"))]
+    pub osc_note_on: String,
+    /// OSC address for note off messages, taking channel (from 0), note and
+    /// velocity
+    #[clap(long, value_parser, default_value_t = String::from("/noteoff"))]
+    pub osc_note_off: String,
+    /// List the sequences of the MIDI file, then exit
+    #[clap(long)]
+    pub list_sequences: bool,
+    /// List MIDI input and output ports, then exit
+    #[clap(long)]
+    pub list_ports: bool,
+}
+
+impl Options {
+    /// Reject options that cannot go together, beyond what clap checks.
+    pub fn check(&self) -> Result<(), clap::Error> {
+        if self.renderer == "curses" && self.stream.as_deref() == Some("-") {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--stream - reads stdin, where the curses renderer reads keys from; \
+                 give a file or use the text renderer",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    parse_within(s, &SPEEDS)
+}
+
+fn parse_tempo(s: &str) -> Result<f64, String> {
+    parse_within(s, &TEMPOS)
+}
+
+fn parse_within(s: &str, range: &RangeInclusive<f64>) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!(
+            "{} is not between {} and {}",
+            value,
+            range.start(),
+            range.end()
+        ))
+    }
 }
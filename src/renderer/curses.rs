@@ -2,20 +2,23 @@
 
 use super::Renderer;
 use crate::{
-    midi::MidiProvider,
+    midi::{Clock, Meta, MidiProvider, Mixer, Part, Score, Transport},
     options::Options,
-    renderer_lib::{pianoroll::PianoRoll, RenderLib},
-    MidiData,
+    renderer_lib::{
+        pianoroll::{DrawBeat, DrawNote, PianoRoll},
+        RenderLib,
+    },
+    MidiData, Notice, SourceId,
 };
-use crossbeam_channel::{select, tick, Receiver};
+use crossbeam_channel::{never, select, tick, Receiver};
 use pancurses::*;
 use std::{
+    collections::BTreeMap,
     sync::{
         atomic::{AtomicBool, Ordering::SeqCst},
         Arc, Mutex,
     },
     thread::{self, JoinHandle},
-    time::Instant,
 };
 use time::Duration;
 
@@ -27,8 +30,92 @@ pub struct Size {
 const COLOR_LIGHTGREEN: i16 = 8;
 const COLOR_DEEPPINK: i16 = 9;
 
+/// How far the arrow keys seek.
+const SEEK_STEP: Duration = Duration::seconds(5);
+/// How much `-` and `+` change the speed.
+const SPEED_STEP: f64 = 0.1;
+
 pub struct CursesRenderer {}
 
+/// What the notices said so far.
+#[derive(Default)]
+struct Status {
+    /// Unplugged input ports
+    disconnected: BTreeMap<SourceId, String>,
+    tempo: Option<Meta>,
+    time_signature: Option<Meta>,
+    key_signature: Option<Meta>,
+    marker: Option<Meta>,
+    /// Names of the tracks of the file
+    tracks: BTreeMap<usize, String>,
+    /// The file playing, and where it is in the playlist
+    song: Option<String>,
+    /// Files of the playlist that could not be played, and why
+    skipped: Vec<String>,
+    /// Where the input is recorded to, or how the last recording went
+    recording: Option<String>,
+    /// Why logging the input stopped
+    log_failed: Option<String>,
+}
+
+/// How the piano roll is drawn, as the options say.
+struct View {
+    look_ahead: Duration,
+    /// Color notes by input port rather than by channel
+    color_by_port: bool,
+}
+
+/// What the keyboard drives: the mix, and playback when playing a file.
+struct Controls {
+    transport: Option<Transport>,
+    mixer: Mixer,
+    /// Tracks and channels to mute or solo
+    parts: Vec<Part>,
+    /// Index of the part `m` and `o` act on
+    selected: Option<usize>,
+}
+
+impl Controls {
+    fn new(transport: Option<Transport>, mixer: Mixer) -> Self {
+        Self {
+            parts: Self::parts(transport.as_ref()),
+            transport,
+            mixer,
+            selected: None,
+        }
+    }
+
+    /// The parts of the file playing, or every channel of live input.
+    fn parts(transport: Option<&Transport>) -> Vec<Part> {
+        match transport {
+            Some(transport) => transport.parts(),
+            None => (0..16).map(Part::Channel).collect(),
+        }
+    }
+
+    /// Playback silences the notes it mutes; live input lets them ring.
+    fn toggle_mute(&self, part: Part) {
+        match &self.transport {
+            Some(transport) => transport.toggle_mute(part),
+            None => self.mixer.toggle_mute(part),
+        }
+    }
+
+    fn toggle_solo(&self, part: Part) {
+        match &self.transport {
+            Some(transport) => transport.toggle_solo(part),
+            None => self.mixer.toggle_solo(part),
+        }
+    }
+
+    fn clear_mix(&self) {
+        match &self.transport {
+            Some(transport) => transport.clear_mix(),
+            None => self.mixer.clear(),
+        }
+    }
+}
+
 impl CursesRenderer {
     fn init() -> Window {
         let window = initscr();
@@ -36,7 +123,9 @@ impl CursesRenderer {
         window.keypad(true);
         nonl();
         cbreak();
-        echo();
+        noecho();
+        // Keys are polled between frames.
+        window.nodelay(true);
 
         // TODO: Configurable colors
         if has_colors() {
@@ -69,32 +158,364 @@ impl CursesRenderer {
         window
     }
 
-    fn draw_buffer(window: &Window, pianoroll: &PianoRoll, midi_in_epoch: &Instant) {
-        let s = window.get_max_yx();
-        let term_size = Size { x: s.1, y: s.0 };
+    /// Track which input ports are currently unplugged, and what the file
+    /// says about the music.
+    fn on_notice(status: &mut Status, notice: Notice) {
+        match notice {
+            Notice::Connected { source, .. } => {
+                status.disconnected.remove(&source);
+            }
+            Notice::Disconnected { source, port } => {
+                status.disconnected.insert(source, port);
+            }
+            Notice::Meta { meta, .. } => match meta {
+                Meta::Tempo(_) => status.tempo = Some(meta),
+                Meta::TimeSignature(..) => status.time_signature = Some(meta),
+                Meta::KeySignature(..) => status.key_signature = Some(meta),
+                Meta::Marker(_) => status.marker = Some(meta),
+                Meta::TrackName { track, name } => {
+                    status.tracks.insert(track, name);
+                }
+                // Lyrics are drawn from the score, in time with the clock.
+                Meta::Lyric(_) | Meta::Text(_) => (),
+            },
+            // Everything that still holds is told again.
+            Notice::Seeked { .. } => {
+                *status = Status {
+                    disconnected: std::mem::take(&mut status.disconnected),
+                    song: status.song.take(),
+                    skipped: std::mem::take(&mut status.skipped),
+                    recording: status.recording.take(),
+                    log_failed: status.log_failed.take(),
+                    ..Default::default()
+                }
+            }
+            Notice::Song {
+                name, index, count, ..
+            } => {
+                status.song = Some(format!("{}/{} {}", index + 1, count, name));
+                status.skipped.clear();
+            }
+            Notice::Skipped { name, reason } => {
+                status.skipped.push(format!("Skipped {}: {}", name, reason));
+            }
+            Notice::Recording { path } => {
+                status.recording = Some(format!("Recording to {}", path));
+            }
+            Notice::Recorded { path, error } => {
+                status.recording = Some(match error {
+                    None => format!("Recorded {}", path),
+                    Some(error) => format!("Could not record {}: {}", path, error),
+                });
+            }
+            Notice::LogFailed { path, error } => {
+                status.log_failed = Some(format!("Stopped logging to {}: {}", path, error));
+            }
+        }
+    }
 
-        let usecs_per_line = 10 * 1000; // 10ms
-        let end = Duration::try_from(midi_in_epoch.elapsed()).unwrap();
-        let begin = end - Duration::microseconds((term_size.x * usecs_per_line) as i64);
+    /// Mix from the keyboard: Tab and Shift-Tab select a track or channel,
+    /// `m` mutes and `o` solos it, `u` hears everything again. Other keys
+    /// drive playback; see [`Self::on_transport_key`].
+    fn on_key(controls: &mut Controls, clock: &Clock, key: Input) {
+        let parts = controls.parts.len();
+        let selected = controls
+            .selected
+            .and_then(|index| controls.parts.get(index).copied());
+        match key {
+            Input::Character('\t') if parts > 0 => {
+                controls.selected = Some(controls.selected.map_or(0, |index| (index + 1) % parts));
+            }
+            Input::KeyBTab if parts > 0 => {
+                controls.selected = Some(
+                    controls
+                        .selected
+                        .map_or(parts - 1, |index| (index + parts - 1) % parts),
+                );
+            }
+            Input::Character('m') => selected
+                .into_iter()
+                .for_each(|part| controls.toggle_mute(part)),
+            Input::Character('o') => selected
+                .into_iter()
+                .for_each(|part| controls.toggle_solo(part)),
+            Input::Character('u') => controls.clear_mix(),
+            key => {
+                if let Some(transport) = &controls.transport {
+                    Self::on_transport_key(transport, clock, key);
+                }
+            }
+        }
+    }
 
-        let draw_notes = pianoroll.get_draw_notes(begin, end, term_size.x as u32);
+    /// Drive playback from the keyboard: space pauses and resumes, `p` and
+    /// Enter pause and play, the arrow keys seek, `[` and `]` go to the
+    /// previous and next bar, `r` restarts and `s` stops. `a` and `b` set
+    /// where a loop starts and ends, `l` plays on without looping. `-` and
+    /// `+` slow down and speed up, `0` goes back to normal speed. `<` and
+    /// `>` go to the previous and next file.
+    fn on_transport_key(transport: &Transport, clock: &Clock, key: Input) {
+        let now = clock.now();
+        match key {
+            Input::Character(' ') => transport.toggle_pause(),
+            Input::Character('p') => transport.pause(),
+            Input::Character('\r') | Input::Character('\n') | Input::KeyEnter => transport.play(),
+            Input::KeyLeft => transport.seek(now - SEEK_STEP),
+            Input::KeyRight => transport.seek(now + SEEK_STEP),
+            Input::Character('[') => transport.seek_to_bar(transport.bar_at(now) - 1),
+            Input::Character(']') => transport.seek_to_bar(transport.bar_at(now) + 1),
+            Input::Character('r') | Input::KeyHome => transport.restart(),
+            Input::Character('s') => transport.stop(),
+            Input::Character('a') => transport.set_loop_start(now),
+            Input::Character('b') => transport.set_loop_end(now),
+            Input::Character('l') => transport.clear_loop(),
+            Input::Character('-') => transport.set_speed(Self::step_speed(clock, -SPEED_STEP)),
+            Input::Character('+') | Input::Character('=') => {
+                transport.set_speed(Self::step_speed(clock, SPEED_STEP))
+            }
+            Input::Character('0') => transport.set_speed(1.0),
+            Input::Character('>') => transport.next(),
+            Input::Character('<') => transport.previous(),
+            _ => (),
+        }
+    }
 
-        window.erase();
+    /// Current speed changed by `step`, without rounding errors piling up.
+    fn step_speed(clock: &Clock, step: f64) -> f64 {
+        ((clock.speed() + step) / SPEED_STEP).round() * SPEED_STEP
+    }
+
+    fn format_time(time: Duration) -> String {
+        format!(
+            "{}:{:04.1}",
+            time.whole_minutes(),
+            time.as_seconds_f32() % 60.0
+        )
+    }
+
+    /// Song position, as minutes, seconds and bar, and the loop if any.
+    fn draw_position(window: &Window, transport: &Transport, clock: &Clock) {
+        let now = clock.now();
+        let mut status = format!("{}  bar {}", Self::format_time(now), transport.bar_at(now));
+        if clock.speed() != 1.0 {
+            status += &format!("  x{:.1}", clock.speed());
+        }
+        if !clock.is_running() {
+            status += "  paused";
+        }
+        if let Some(range) = transport.loop_range() {
+            // The end is where the next bar would start.
+            status += &format!(
+                "  loop {}-{} (bars {}-{})",
+                Self::format_time(range.start),
+                Self::format_time(range.end),
+                transport.bar_at(range.start),
+                transport.bar_at(range.end - Duration::MILLISECOND)
+            );
+        }
+        window.attrset(A_NORMAL);
+        window.mvaddstr(0, 0, status);
+    }
 
-        draw_notes.iter().for_each(|note| {
+    /// The file playing, tempo, signatures and marker, and the track names
+    /// under them.
+    fn draw_music(window: &Window, status: &Status) {
+        let music: Vec<String> = status
+            .song
+            .iter()
+            .cloned()
+            .chain(
+                [
+                    &status.tempo,
+                    &status.time_signature,
+                    &status.key_signature,
+                    &status.marker,
+                ]
+                .iter()
+                .filter_map(|meta| meta.as_ref().map(|meta| meta.to_string())),
+            )
+            .collect();
+        let tracks: Vec<String> = status
+            .tracks
+            .iter()
+            .filter(|(_, name)| !name.is_empty())
+            .map(|(track, name)| format!("{} {}", track + 1, name))
+            .collect();
+        window.attrset(A_NORMAL);
+        window.mvaddstr(1, 0, music.join("  "));
+        window.mvaddstr(2, 0, tracks.join("  "));
+    }
+
+    /// The line of lyrics being sung, centered above the bar numbers: sung
+    /// syllables in bold, the one sung now reversed.
+    fn draw_lyrics(window: &Window, term_size: &Size, score: &Score, clock: &Clock) {
+        let Some((line, reached)) = score.lyric_line(clock.now()) else {
+            return;
+        };
+        let width: usize = line
+            .iter()
+            .map(|syllable| syllable.text.chars().count())
+            .sum();
+        window.mv(term_size.y - 2, ((term_size.x - width as i32) / 2).max(0));
+        line.iter().enumerate().for_each(|(i, syllable)| {
+            window.attrset(match (i + 1).cmp(&reached) {
+                std::cmp::Ordering::Less => A_BOLD,
+                std::cmp::Ordering::Equal => A_REVERSE,
+                std::cmp::Ordering::Greater => A_NORMAL,
+            });
+            window.addstr(&syllable.text);
+        });
+        window.attrset(A_NORMAL);
+    }
+
+    /// The selected part and the muted and soloed ones, on the row under
+    /// the track names.
+    fn draw_mixer(window: &Window, controls: &Controls, status: &Status) {
+        let mixer = &controls.mixer;
+        window.mv(3, 0);
+        controls
+            .parts
+            .iter()
+            .enumerate()
+            .filter(|&(index, &part)| {
+                controls.selected == Some(index) || mixer.is_muted(part) || mixer.is_soloed(part)
+            })
+            .for_each(|(index, &part)| {
+                let mut label = part.to_string();
+                if let Part::Track(track) = part {
+                    if let Some(name) = status.tracks.get(&track).filter(|name| !name.is_empty()) {
+                        label += &format!(" {}", name);
+                    }
+                }
+                if mixer.is_muted(part) {
+                    label += " muted";
+                }
+                if mixer.is_soloed(part) {
+                    label += " solo";
+                }
+                window.attrset(match controls.selected == Some(index) {
+                    true => A_REVERSE,
+                    false => A_NORMAL,
+                });
+                window.addstr(label);
+                window.attrset(A_NORMAL);
+                window.addstr("  ");
+            });
+    }
+
+    fn draw_status(window: &Window, term_size: &Size, status: &Status) {
+        status
+            .disconnected
+            .values()
+            .map(|port| format!("{} disconnected", port))
+            .chain(status.skipped.iter().cloned())
+            .chain(status.recording.iter().cloned())
+            .chain(status.log_failed.iter().cloned())
+            .enumerate()
+            .for_each(|(i, message)| {
+                window.attrset(A_REVERSE);
+                window.mvaddstr(term_size.y - 1 - i as i32, 0, message);
+            });
+        window.attrset(A_NORMAL);
+    }
+
+    /// Draw `notes` from column `offset` on, with `symbol`, in the color of
+    /// their channel or input port.
+    fn draw_notes(
+        window: &Window,
+        term_size: &Size,
+        notes: &[DrawNote],
+        offset: i32,
+        symbol: &str,
+        attributes: chtype,
+        color_by_port: bool,
+    ) {
+        notes.iter().for_each(|note| {
             if note.end > 0 {
-                window.attrset(COLOR_PAIR(note.channel as chtype));
+                let color = match color_by_port {
+                    true => note.source % 16,
+                    false => note.channel as usize,
+                };
+                window.attrset(COLOR_PAIR(color as chtype) | attributes);
                 let mut begin = note.begin;
                 if begin < 0 {
                     begin = 0;
                 }
                 let y: i32 = ((term_size.y / 2) - note.note as i32 + 64) as i32;
-                let x: i32 = begin;
+                let x: i32 = offset + begin;
                 let length: usize = (note.end - begin) as usize;
-                let s: String = "|".repeat(length);
+                let s: String = symbol.repeat(length);
                 window.mvaddstr(y, x, s);
             }
         });
+    }
+
+    /// Beat lines behind the notes, bar lines and bar numbers on the bottom row.
+    fn draw_grid(window: &Window, term_size: &Size, beats: &[DrawBeat]) {
+        window.attrset(A_DIM);
+        beats.iter().for_each(|beat| {
+            window.mv(0, beat.x);
+            match beat.bar {
+                Some(bar) => {
+                    window.vline(ACS_VLINE(), term_size.y - 1);
+                    window.mvaddstr(term_size.y - 1, beat.x, bar.to_string());
+                }
+                None => {
+                    window.vline('.', term_size.y - 1);
+                }
+            }
+        });
+        window.attrset(A_NORMAL);
+    }
+
+    fn draw_buffer(
+        window: &Window,
+        pianoroll: &PianoRoll,
+        clock: &Clock,
+        controls: Option<&Controls>,
+        score: Option<&Score>,
+        view: &View,
+        status: &Status,
+    ) {
+        let s = window.get_max_yx();
+        let term_size = Size { x: s.1, y: s.0 };
+
+        let usecs_per_line = 10 * 1000; // 10ms
+        let column = Duration::microseconds(usecs_per_line as i64);
+        // Upcoming notes get at most half the screen, right of the playhead.
+        let ahead = ((view.look_ahead / column) as i32).min(term_size.x / 2);
+        let played = term_size.x - ahead;
+        let end = clock.now();
+        let begin = end - column * played;
+
+        let draw_notes = pianoroll.get_draw_notes(begin, end, played as u32);
+        // Nothing after the loop is coming.
+        let transport = controls.and_then(|controls| controls.transport.as_ref());
+        let until = match transport.and_then(|transport| transport.loop_range()) {
+            Some(range) if end < range.end => (end + column * ahead).min(range.end),
+            _ => end + column * ahead,
+        };
+        let upcoming = pianoroll.get_upcoming_notes(end, until, ((until - end) / column) as u32);
+        let beats = pianoroll.get_draw_beats(begin, end + column * ahead, term_size.x as u32);
+
+        window.erase();
+
+        Self::draw_grid(window, &term_size, &beats);
+        let by_port = view.color_by_port;
+        Self::draw_notes(window, &term_size, &draw_notes, 0, "|", A_NORMAL, by_port);
+        Self::draw_notes(window, &term_size, &upcoming, played, ":", A_DIM, by_port);
+
+        Self::draw_music(window, status);
+        if let Some(transport) = transport {
+            Self::draw_position(window, transport, clock);
+        }
+        if let Some(controls) = controls {
+            Self::draw_mixer(window, controls, status);
+        }
+        if let Some(score) = score {
+            Self::draw_lyrics(window, &term_size, score, clock);
+        }
+        Self::draw_status(window, &term_size, status);
 
         window.refresh();
     }
@@ -102,23 +523,66 @@ impl CursesRenderer {
 
 impl<T: MidiProvider> Renderer<T> for CursesRenderer {
     fn init(
-        _opts: &Options,
+        opts: &Options,
         midi: &T,
         quit: Arc<AtomicBool>,
         handlers: &mut Vec<JoinHandle<()>>,
     ) -> Self {
         let midi_recv = midi.get_midi_in_recv();
-        let epoch = midi.get_epoch();
+        let mut notice_recv = midi.get_notice_recv();
+        let clock = midi.get_clock();
+        let transport = midi.get_transport();
+        let mut controls = midi
+            .get_mixer()
+            .map(|mixer| Controls::new(transport, mixer));
+        let mut score = midi.get_score();
+        let recorder = midi.get_recorder();
+        let view = View {
+            look_ahead: Duration::milliseconds(opts.look_ahead as i64),
+            color_by_port: opts.color_by == "port",
+        };
+        let sources = opts.show_port.clone();
         handlers.push(thread::spawn(move || {
             let window = Self::init();
-            let render_lib = PianoRoll::new(&midi_recv, quit.clone());
+            let mut status = Status::default();
+            let mut render_lib = PianoRoll::new(&midi_recv, quit.clone())
+                .with_score(score.clone())
+                .with_sources(sources)
+                .with_mixer(controls.as_ref().map(|controls| controls.mixer.clone()));
             // 20 fps
             let tick = tick(Duration::milliseconds(50).unsigned_abs());
 
             loop {
                 select! {
                     recv(tick) -> _ => {
-                        Self::draw_buffer(&window, &render_lib, &epoch);
+                        while let Some(key) = window.getch() {
+                            // `R` starts and stops recording live input.
+                            match (&recorder, &mut controls) {
+                                (Some(recorder), _) if key == Input::Character('R') => recorder.toggle(),
+                                (_, Some(controls)) => Self::on_key(controls, &clock, key),
+                                _ => (),
+                            }
+                        }
+                        Self::draw_buffer(&window, &render_lib, &clock, controls.as_ref(), score.as_deref(), &view, &status);
+                    },
+                    recv(notice_recv) -> notice => {
+                        match notice {
+                            Ok(notice) => {
+                                if let Notice::Seeked { position } = &notice {
+                                    render_lib.seek(*position);
+                                }
+                                if let Notice::Song { score: next, .. } = &notice {
+                                    score = Some(next.clone());
+                                    render_lib.set_score(score.clone());
+                                    if let Some(controls) = &mut controls {
+                                        controls.parts = Controls::parts(controls.transport.as_ref());
+                                        controls.selected = None;
+                                    }
+                                }
+                                Self::on_notice(&mut status, notice);
+                            }
+                            Err(_) => notice_recv = never(),
+                        }
                     },
                 }
                 if quit.load(SeqCst) {
@@ -5,11 +5,17 @@ use std::{
     thread::{self, JoinHandle},
 };
 
-use crate::{midi::MidiProvider, options::Options, renderer::Renderer, MidiData};
-use crossbeam_channel::{select, Receiver, RecvError};
+use crate::{
+    midi::{Meta, MidiProvider, Score},
+    options::Options,
+    renderer::Renderer,
+    MidiData, Notice,
+};
+use crossbeam_channel::{never, select, Receiver, RecvError};
 use midi_msg::{self, MidiMsg, ReceiverContext};
 use std::sync::atomic::Ordering::SeqCst;
 use std::time::Instant;
+use time::Duration;
 
 /// Rendering as a text
 pub struct TextRenderer {}
@@ -27,28 +33,108 @@ impl TextRenderer {
         print!("{}", message.trim_end());
         std::io::stdout().flush().unwrap();
     }
+
+    /// Output the line of lyrics sung at `time`, the syllable sung now
+    /// reversed.
+    fn draw_lyrics(score: &Score, time: Duration) {
+        let line = match score.lyric_line(time) {
+            Some((line, reached)) => line
+                .iter()
+                .enumerate()
+                .map(|(i, syllable)| match i + 1 == reached {
+                    true => format!("{}[7m{}{}[0m", 27 as char, syllable.text, 27 as char),
+                    false => syllable.text.clone(),
+                })
+                .collect(),
+            None => String::new(),
+        };
+        print!("\r{}[K{}", 27 as char, line);
+        std::io::stdout().flush().unwrap();
+    }
+
+    /// Output notice on its own line, above the running message.
+    fn draw_notice(notice: &Notice) {
+        let message = match notice {
+            Notice::Connected { port, .. } => format!("{} connected", port),
+            Notice::Disconnected { port, .. } => format!("{} disconnected", port),
+            Notice::Meta { meta, timestamp } => {
+                let label = match meta {
+                    Meta::Tempo(_) => "Tempo",
+                    Meta::TimeSignature(..) => "Time signature",
+                    Meta::KeySignature(..) => "Key",
+                    Meta::Marker(_) => "Marker",
+                    Meta::TrackName { .. } => "Track",
+                    Meta::Lyric(_) => "Lyric",
+                    Meta::Text(_) => "Text",
+                };
+                format!("{:.1}s {} {}", timestamp.as_seconds_f64(), label, meta)
+            }
+            Notice::Seeked { position } => format!("Seeked to {:.1}s", position.as_seconds_f64()),
+            Notice::Song {
+                name, index, count, ..
+            } => format!("Playing {}/{} {}", index + 1, count, name),
+            Notice::Skipped { name, reason } => format!("Skipped {}: {}", name, reason),
+            Notice::Recording { path } => format!("Recording to {}", path),
+            Notice::Recorded { path, error: None } => format!("Recorded {}", path),
+            Notice::Recorded {
+                path,
+                error: Some(error),
+            } => format!("Could not record {}: {}", path, error),
+            Notice::LogFailed { path, error } => format!("Stopped logging to {}: {}", path, error),
+        };
+        println!("\r{}[K{}", 27 as char, message);
+    }
 }
 
 impl<T: MidiProvider> Renderer<T> for TextRenderer {
     fn init(
-        _opts: &Options,
+        opts: &Options,
         midi: &T,
         quit: Arc<AtomicBool>,
         handlers: &mut Vec<JoinHandle<()>>,
     ) -> TextRenderer {
         let midi_recv = midi.get_midi_in_recv();
+        let mut notice_recv = midi.get_notice_recv();
+        // Lyrics take the place of MIDI messages.
+        let show_lyrics = opts.lyrics;
+        let with_lyrics = move |score: Option<Arc<Score>>| {
+            score.filter(|score| show_lyrics && !score.lyrics.is_empty())
+        };
+        let mut lyrics = with_lyrics(midi.get_score());
         handlers.push(thread::spawn(move || loop {
             select! {
                 recv(midi_recv) -> midi => {
                     match midi {
                         Ok(midi) => {
-                            Self::draw(&midi);
+                            if lyrics.is_none() {
+                                Self::draw(&midi);
+                            }
                         },
                         Err(RecvError) => {
                             break;
                         },
                     }
                 }
+                recv(notice_recv) -> notice => {
+                    match notice {
+                        Ok(notice) => {
+                            if let Notice::Song { score, .. } = &notice {
+                                lyrics = with_lyrics(Some(score.clone()));
+                            }
+                            match (&lyrics, notice) {
+                            (Some(score), Notice::Meta { meta: Meta::Lyric(_) | Meta::Text(_), timestamp }) => {
+                                Self::draw_lyrics(score, timestamp);
+                            }
+                            (Some(score), Notice::Seeked { position }) => {
+                                Self::draw_notice(&Notice::Seeked { position });
+                                Self::draw_lyrics(score, position);
+                            }
+                            (_, notice) => Self::draw_notice(&notice),
+                            }
+                        }
+                        Err(_) => notice_recv = never(),
+                    }
+                }
             }
             if quit.load(SeqCst) {
                 break;
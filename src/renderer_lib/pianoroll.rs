@@ -11,8 +11,11 @@ use time::Duration;
 
 use crossbeam_channel::{select, tick, Receiver};
 
-use crate::MidiData;
-use midi_msg::{Channel, ChannelVoiceMsg, MidiMsg};
+use crate::{
+    midi::{Beat, Mixer, Score, SongNote},
+    MidiData, SourceId,
+};
+use midi_msg::{Channel, ChannelVoiceMsg, MidiMsg, SystemCommonMsg, SystemRealTimeMsg};
 
 use super::RenderLib;
 
@@ -23,10 +26,33 @@ struct Note {
     channel: Channel,
     note: u8,
     velocity: u8,
+    source: SourceId,
+}
+
+/// MIDI clock ticks per quarter note.
+const CLOCKS_PER_BEAT: u32 = 24;
+/// MIDI clock does not tell the time signature, so bars are taken as 4/4.
+const BEATS_PER_BAR: u32 = 4;
+/// A song position pointer counts sixteenth notes.
+const CLOCKS_PER_SIXTEENTH: u32 = 6;
+
+/// Beats counted from incoming MIDI clock.
+#[derive(Default)]
+struct LiveBeats {
+    beats: Vec<Beat>,
+    /// MIDI clock ticks since the start of the song
+    clocks: u32,
 }
 
 pub struct PianoRoll {
     pianoroll: Arc<RwLock<Vec<Note>>>,
+    /// Notes and beats known before they are played
+    score: Option<Arc<Score>>,
+    /// Which notes of the score are played
+    mixer: Option<Mixer>,
+    /// Input ports whose notes are drawn, all if empty
+    sources: Vec<SourceId>,
+    live_beats: Arc<RwLock<LiveBeats>>,
     pub handler: JoinHandle<()>,
 }
 
@@ -38,11 +64,18 @@ pub struct Atom {
 
 pub type Line = Vec<Atom>;
 
+pub struct DrawBeat {
+    pub x: i32,
+    /// Number of the bar the beat starts
+    pub bar: Option<usize>,
+}
+
 pub struct DrawNote {
     pub begin: i32,
     pub end: i32,
     pub channel: Channel,
     pub note: u8,
+    pub source: SourceId,
 }
 
 impl PianoRoll {
@@ -57,11 +90,12 @@ impl PianoRoll {
         let pr = p.read().unwrap();
 
         let target_notes = pr.iter().filter(|note| {
-            note.begin < range_end
-                || (match note.end {
-                    Some(note_end) => range_begin < note_end,
-                    None => true,
-                })
+            self.shows(note.source)
+                && (note.begin < range_end
+                    || (match note.end {
+                        Some(note_end) => range_begin < note_end,
+                        None => true,
+                    }))
         });
 
         let sampling_timestamp = range_begin;
@@ -73,6 +107,7 @@ impl PianoRoll {
                 end: ((end - sampling_timestamp) / interval) as i32,
                 channel: note.channel,
                 note: note.note,
+                source: note.source,
             };
             notes.push(draw_note);
         });
@@ -80,6 +115,133 @@ impl PianoRoll {
         notes
     }
 
+    /// Also show the notes of `score` before they are played, and take its
+    /// beats rather than counting MIDI clock.
+    pub fn with_score(mut self, score: Option<Arc<Score>>) -> Self {
+        self.set_score(score);
+        self
+    }
+
+    /// Take `score` from now on, as when another file plays.
+    pub fn set_score(&mut self, score: Option<Arc<Score>>) {
+        self.score = score;
+    }
+
+    /// Draw only the notes of `sources`, or of every input port if empty.
+    pub fn with_sources(mut self, sources: Vec<SourceId>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    fn shows(&self, source: SourceId) -> bool {
+        self.sources.is_empty() || self.sources.contains(&source)
+    }
+
+    /// Leave out the notes of the score that `mixer` mutes.
+    pub fn with_mixer(mut self, mixer: Option<Mixer>) -> Self {
+        self.mixer = mixer;
+        self
+    }
+
+    /// Notes of the score sounding between `range_begin` and `range_end`,
+    /// cut off at both, unless muted.
+    pub fn get_upcoming_notes(
+        &self,
+        range_begin: Duration,
+        range_end: Duration,
+        sample_num: u32,
+    ) -> Vec<DrawNote> {
+        let score = match &self.score {
+            // The score plays as the first source.
+            Some(score) if range_begin < range_end && sample_num > 0 && self.shows(0) => score,
+            _ => return Vec::new(),
+        };
+        let notes = &score.notes;
+        let last = notes.partition_point(|note| note.begin < range_end);
+        let interval = (range_end - range_begin) / sample_num;
+        notes[..last]
+            .iter()
+            .filter(|note| note.end > range_begin)
+            .filter(|note| {
+                self.mixer
+                    .as_ref()
+                    .is_none_or(|mixer| mixer.is_audible(note.track, note.channel))
+            })
+            .map(|note: &SongNote| DrawNote {
+                begin: ((note.begin.max(range_begin) - range_begin) / interval) as i32,
+                end: ((note.end.min(range_end) - range_begin) / interval) as i32,
+                channel: Channel::from_u8(note.channel),
+                note: note.key,
+                source: 0,
+            })
+            .collect()
+    }
+
+    /// Beats between `range_begin` and `range_end`, from the score if there
+    /// is one, or else from MIDI clock.
+    pub fn get_draw_beats(
+        &self,
+        range_begin: Duration,
+        range_end: Duration,
+        sample_num: u32,
+    ) -> Vec<DrawBeat> {
+        if range_end <= range_begin || sample_num == 0 {
+            return Vec::new();
+        }
+        let live_beats = self.live_beats.read().unwrap();
+        let beats = match &self.score {
+            Some(score) => &score.beats,
+            None => &live_beats.beats,
+        };
+        let first = beats.partition_point(|beat| beat.time < range_begin);
+        let interval = (range_end - range_begin) / sample_num;
+        beats[first..]
+            .iter()
+            .take_while(|beat| beat.time < range_end)
+            .map(|beat| DrawBeat {
+                x: ((beat.time - range_begin) / interval) as i32,
+                bar: beat.bar,
+            })
+            .collect()
+    }
+
+    /// Count beats and bars from MIDI clock, starting over at Start and
+    /// following song position pointers.
+    fn on_clock(live_beats: &mut LiveBeats, midi: &MidiData) {
+        match MidiMsg::from_midi(midi.message.as_slice()) {
+            Ok((
+                MidiMsg::SystemRealTime {
+                    msg: SystemRealTimeMsg::TimingClock,
+                },
+                _,
+            )) => {
+                if live_beats.clocks.is_multiple_of(CLOCKS_PER_BEAT) {
+                    let beat = live_beats.clocks / CLOCKS_PER_BEAT;
+                    live_beats.beats.push(Beat {
+                        time: midi.timestamp,
+                        bar: beat
+                            .is_multiple_of(BEATS_PER_BAR)
+                            .then_some((beat / BEATS_PER_BAR) as usize + 1),
+                    });
+                }
+                live_beats.clocks += 1;
+            }
+            Ok((
+                MidiMsg::SystemRealTime {
+                    msg: SystemRealTimeMsg::Start,
+                },
+                _,
+            )) => live_beats.clocks = 0,
+            Ok((
+                MidiMsg::SystemCommon {
+                    msg: SystemCommonMsg::SongPosition(position),
+                },
+                _,
+            )) => live_beats.clocks = position as u32 * CLOCKS_PER_SIXTEENTH,
+            _ => (),
+        }
+    }
+
     pub fn draw(&self, range_begin: Duration, range_end: Duration, sample_num: u32) -> Vec<Line> {
         let mut buf = Vec::<Line>::new();
         let p = self.pianoroll.clone();
@@ -120,30 +282,48 @@ impl PianoRoll {
         buf
     }
 
+    /// Forget what was drawn after `position` and end every note held
+    /// there, as a file was seeked to it.
+    ///
+    /// Notes played from `position` on may already be in, so they are kept.
+    pub fn seek(&self, position: Duration) {
+        let mut pianoroll = self.pianoroll.write().unwrap();
+        pianoroll.retain(|note| note.begin <= position);
+        pianoroll.iter_mut().for_each(|note| {
+            if note.begin < position && note.end.is_none_or(|end| end > position) {
+                note.end = Some(position);
+            }
+        });
+    }
+
     fn on_event(pianoroll: &mut Vec<Note>, midi: &MidiData) {
         let result = MidiMsg::from_midi(midi.message.as_slice());
 
-        match result {
-            Ok(m) => match m.0 {
-                MidiMsg::ChannelVoice { channel, msg } => match msg {
-                    ChannelVoiceMsg::NoteOn { note, velocity } => pianoroll.push(Note {
-                        begin: midi.timestamp,
-                        end: None,
-                        channel,
-                        note,
-                        velocity,
-                    }),
-                    ChannelVoiceMsg::NoteOff { note, .. } => {
-                        pianoroll
-                            .iter_mut()
-                            .rfind(|n| (n.channel == channel && n.note == note))
-                            .map(|n| n.end = Some(midi.timestamp));
-                    }
-                    _ => (),
-                },
+        if let Ok((MidiMsg::ChannelVoice { channel, msg }, _)) = result {
+            match msg {
+                ChannelVoiceMsg::NoteOn { note, velocity } => pianoroll.push(Note {
+                    begin: midi.timestamp,
+                    end: None,
+                    channel,
+                    note,
+                    velocity,
+                    source: midi.source,
+                }),
+                // Notes cut short by a seek are ended already.
+                ChannelVoiceMsg::NoteOff { note, .. } => {
+                    pianoroll
+                        .iter_mut()
+                        .rfind(|n| {
+                            n.source == midi.source
+                                && n.channel == channel
+                                && n.note == note
+                                && n.end.is_none()
+                                && n.begin <= midi.timestamp
+                        })
+                        .map(|n| n.end = Some(midi.timestamp));
+                }
                 _ => (),
-            },
-            _ => (),
+            }
         }
     }
 }
@@ -154,16 +334,21 @@ impl RenderLib for PianoRoll {
         let midi_recv = midi_recv.clone();
         let tick = tick(Duration::seconds(2).unsigned_abs());
 
+        let live_beats = Arc::new(RwLock::new(LiveBeats::default()));
+
         let p = pianoroll.clone();
+        let b = live_beats.clone();
         let handler = thread::spawn(move || loop {
             select! {
                 recv(midi_recv) -> midi => {
                     match midi {
                         Ok (midi) => {
                             Self::on_event(&mut p.write().unwrap(), &midi);
+                            Self::on_clock(&mut b.write().unwrap(), &midi);
                         }
                         Err(_) => {
-
+                            // The provider has gone away; the roll keeps what it got.
+                            break;
                         }
                     }
                 },
@@ -174,14 +359,25 @@ impl RenderLib for PianoRoll {
             }
         });
 
-        Self { pianoroll, handler }
+        Self {
+            pianoroll,
+            score: None,
+            mixer: None,
+            sources: Vec::new(),
+            live_beats,
+            handler,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::PianoRoll;
-    use crate::{renderer_lib::RenderLib, MidiData};
+    use crate::{
+        midi::{Mixer, Part, Score, SongNote},
+        renderer_lib::RenderLib,
+        MidiData,
+    };
     use crossbeam_channel::{bounded, unbounded};
     use midi_msg::{MidiMsg, ReceiverContext};
     use std::{
@@ -208,6 +404,7 @@ mod tests {
                     0x64, // Velocity
                 ],
                 timestamp: Duration::seconds(2),
+                source: 0,
             },
             MidiData {
                 message: vec![
@@ -216,6 +413,7 @@ mod tests {
                     0x64, // Velocity
                 ],
                 timestamp: Duration::seconds(3),
+                source: 0,
             },
             MidiData {
                 message: vec![
@@ -224,6 +422,7 @@ mod tests {
                     0x64, // Velocity
                 ],
                 timestamp: Duration::seconds(4),
+                source: 0,
             },
             MidiData {
                 message: vec![
@@ -232,6 +431,7 @@ mod tests {
                     0x64, // Velocity
                 ],
                 timestamp: Duration::seconds(5),
+                source: 0,
             },
             MidiData {
                 message: vec![
@@ -240,6 +440,7 @@ mod tests {
                     0x64, // Velocity
                 ],
                 timestamp: Duration::seconds(6),
+                source: 0,
             },
             MidiData {
                 message: vec![
@@ -248,6 +449,7 @@ mod tests {
                     0x64, // Velocity
                 ],
                 timestamp: Duration::seconds(7),
+                source: 0,
             },
         ];
 
@@ -274,4 +476,163 @@ mod tests {
 
         quit.store(true, SeqCst);
     }
+
+    #[test]
+    fn pianoroll_seek() {
+        let quit = Arc::new(AtomicBool::new(false));
+        let (midi_snd, midi_recv) = unbounded();
+        let pianoroll = PianoRoll::new(&midi_recv, quit.clone());
+
+        let midi_data = [
+            // Ends after the reset
+            (vec![0x90, 0x40, 0x64], 1),
+            (vec![0x80, 0x40, 0x64], 5),
+            // Held at the reset
+            (vec![0x90, 0x41, 0x64], 2),
+            // Starts after the reset
+            (vec![0x90, 0x42, 0x64], 4),
+            // A System Reset from a device is no seek.
+            (vec![0xFF], 3),
+        ];
+        midi_data.into_iter().for_each(|(message, seconds)| {
+            let _ = midi_snd.send(MidiData {
+                message,
+                timestamp: Duration::seconds(seconds),
+                source: 0,
+            });
+        });
+
+        sleep(Duration::seconds(1).unsigned_abs());
+
+        let notes = || {
+            pianoroll
+                .get_draw_notes(Duration::seconds(0), Duration::seconds(10), 10)
+                .iter()
+                .map(|note| (note.note, note.begin, note.end))
+                .collect::<Vec<(u8, i32, i32)>>()
+        };
+        assert_eq!(notes(), vec![(0x40, 1, 5), (0x41, 2, 10), (0x42, 4, 10)]);
+        pianoroll.seek(Duration::seconds(3));
+        assert_eq!(notes(), vec![(0x40, 1, 3), (0x41, 2, 3)]);
+
+        // Played from where it lands before the seek is told.
+        let _ = midi_snd.send(MidiData {
+            message: vec![0x90, 0x43, 0x64],
+            timestamp: Duration::seconds(3),
+            source: 0,
+        });
+        sleep(Duration::milliseconds(100).unsigned_abs());
+        pianoroll.seek(Duration::seconds(3));
+        assert_eq!(notes()[2], (0x43, 3, 10));
+
+        quit.store(true, SeqCst);
+    }
+
+    #[test]
+    fn pianoroll_sources() {
+        let quit = Arc::new(AtomicBool::new(false));
+        let (midi_snd, midi_recv) = unbounded();
+        let pianoroll = PianoRoll::new(&midi_recv, quit.clone()).with_sources(vec![1, 2]);
+
+        [(0x40, 0), (0x41, 1), (0x42, 2)]
+            .into_iter()
+            .for_each(|(note, source)| {
+                let _ = midi_snd.send(MidiData {
+                    message: vec![0x90, note, 0x64],
+                    timestamp: Duration::seconds(1),
+                    source,
+                });
+            });
+
+        sleep(Duration::seconds(1).unsigned_abs());
+
+        let notes = pianoroll.get_draw_notes(Duration::seconds(0), Duration::seconds(10), 10);
+        let notes: Vec<(u8, usize)> = notes.iter().map(|note| (note.note, note.source)).collect();
+        assert_eq!(notes, vec![(0x41, 1), (0x42, 2)]);
+
+        quit.store(true, SeqCst);
+    }
+
+    #[test]
+    fn pianoroll_upcoming() {
+        let quit = Arc::new(AtomicBool::new(false));
+        let (_midi_snd, midi_recv) = unbounded();
+        let note = |begin, end, key| SongNote {
+            begin: Duration::seconds(begin),
+            end: Duration::seconds(end),
+            track: 0,
+            channel: 1,
+            key,
+        };
+        let notes = vec![
+            note(0, 1, 59),
+            note(0, 2, 60),
+            note(3, 4, 62),
+            note(5, 9, 64),
+            note(10, 11, 65),
+        ];
+        let score = Arc::new(Score {
+            notes,
+            ..Default::default()
+        });
+        let mixer = Mixer::new();
+        let pianoroll = PianoRoll::new(&midi_recv, quit.clone())
+            .with_score(Some(score))
+            .with_mixer(Some(mixer.clone()));
+
+        let upcoming = || {
+            pianoroll
+                .get_upcoming_notes(Duration::seconds(1), Duration::seconds(7), 6)
+                .iter()
+                .map(|note| (note.note, note.begin, note.end))
+                .collect::<Vec<(u8, i32, i32)>>()
+        };
+        // Held notes and late ones are cut off; ended ones are left out.
+        assert_eq!(upcoming(), vec![(60, 0, 1), (62, 2, 3), (64, 4, 6)]);
+        mixer.toggle_mute(Part::Channel(1));
+        assert!(upcoming().is_empty());
+
+        quit.store(true, SeqCst);
+    }
+
+    #[test]
+    fn pianoroll_midi_clock() {
+        let quit = Arc::new(AtomicBool::new(false));
+        let (midi_snd, midi_recv) = unbounded();
+        let pianoroll = PianoRoll::new(&midi_recv, quit.clone());
+
+        let send = |message: Vec<u8>, millis| {
+            let _ = midi_snd.send(MidiData {
+                message,
+                timestamp: Duration::milliseconds(millis),
+                source: 0,
+            });
+        };
+        // Joined in the middle of the second beat, then started over.
+        send(vec![0xF2, 6, 0], 0);
+        (0..24).for_each(|clock| send(vec![0xF8], 10 * clock));
+        send(vec![0xFA], 240);
+        (0..24 * 5).for_each(|clock| send(vec![0xF8], 250 + 10 * clock));
+
+        sleep(Duration::seconds(1).unsigned_abs());
+
+        let beats: Vec<(i32, Option<usize>)> = pianoroll
+            .get_draw_beats(Duration::ZERO, Duration::seconds(2), 200)
+            .iter()
+            .map(|beat| (beat.x, beat.bar))
+            .collect();
+        assert_eq!(
+            beats,
+            vec![
+                (12, None),
+                (25, Some(1)),
+                (49, None),
+                (73, None),
+                (97, None),
+                (121, Some(2)),
+            ]
+        );
+
+        quit.store(true, SeqCst);
+    }
 }